@@ -14,7 +14,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let start = 1701126000000; // 2023-11-28 00:00:00
 
     let response = x.socket.get_chart_last_request("PKN.PL_9", start, Period::W1).await?;
-    println!("{:?}", response.return_data);
+    for bar in response.return_data.bars() {
+        println!("{:?}", bar);
+    }
 
     Ok(())
 }
@@ -0,0 +1,29 @@
+use xapi;
+use xapi::{Event, Subscription, Supervisor};
+
+use std::error::Error;
+use std::fs;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let json = fs::read_to_string("credentials.json")?;
+    let credentials = xapi::Credentials::from(&json)?;
+
+    let x = Supervisor::connect(&credentials).await?;
+
+    x.add_subscription(&Subscription::Candles { symbol: String::from("BITCOIN") })
+        .await?;
+    x.add_subscription(&Subscription::TickPrices {
+        symbol: String::from("ETHEREUM"),
+        min_arrival_time: 0,
+        max_level: 0,
+    })
+    .await?;
+
+    loop {
+        match x.listen().await? {
+            Event::Record(record) => println!("{:?}", record),
+            Event::Reconnected => println!("Reconnected, subscriptions restored"),
+        }
+    }
+}
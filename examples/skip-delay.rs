@@ -8,7 +8,8 @@ use std::fs;
     but if it happens 6 times in a row the connection is dropped.
 
     The xapi takes care of the necessary delays by itself.
-    But this restriction can be omitted by using the skip_delay() method.
+    But this restriction can be omitted by using the skip_delay() method,
+    or relaxed by connecting with a RateLimiter allowing short bursts.
 */
 
 #[tokio::main]
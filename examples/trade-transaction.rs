@@ -49,7 +49,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
         RequestStatus::Rejected => {
             println!("The transaction has been rejected {:?}", status.message)
         }
-        RequestStatus::Invalid => {
+        RequestStatus::Invalid | RequestStatus::Unknown(_) => {
             println!("Invalid transaction status {:?}", status.message)
         }
     };
@@ -0,0 +1,280 @@
+//! Recording and replaying of WebSocket sessions.
+//!
+//! A `Recorder` writes every text frame sent and received by the socket and the stream connection
+//! to a JSON lines cassette. A `Cassette` loaded from such a file replaces the network in `ConnectionConfig::replay`:
+//! each received frame is fed back once the frames sent before it in the recording have been sent again.
+//! Requests are tagged in the order they are sent, so the replayed application should send the same requests
+//! in the same order, as it did when recording.
+
+use crate::connection::{Sink, Source, KEEPALIVE_TAG};
+use crate::redact::redact;
+
+use futures::StreamExt;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::tungstenite::Error as WsError;
+
+/// Connection a frame was sent or received on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Channel {
+    Socket,
+    Stream,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Single line of a cassette.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Frame {
+    /// Milliseconds since the Unix epoch.
+    pub time: u64,
+    pub channel: Channel,
+    pub direction: Direction,
+    pub data: String,
+}
+
+/// Writer of cassettes. Clones write to the same cassette.
+#[derive(Clone)]
+pub struct Recorder {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder").finish_non_exhaustive()
+    }
+}
+
+impl Recorder {
+    /// Creates the cassette file, truncating an existing one.
+    pub fn create<P: AsRef<Path>>(path: P) -> std::io::Result<Recorder> {
+        Ok(Self::new(LineWriter::new(File::create(path)?)))
+    }
+
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Recorder {
+        Recorder { writer: Arc::new(Mutex::new(Box::new(writer))) }
+    }
+
+    /// Writes the frame. The password and the `streamSessionId` are replaced with `***`.
+    /// Keepalive pings and their responses are skipped, as no pings are sent when replaying.
+    pub(crate) fn record(&self, channel: Channel, direction: Direction, data: &str) {
+        if is_keepalive(data) {
+            return;
+        }
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let frame = Frame { time, channel, direction, data: redact(data) };
+        let Ok(line) = serde_json::to_string(&frame) else {
+            return;
+        };
+
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(err) = writeln!(writer, "{}", line) {
+            warn!("Failed to record frame: {}", err);
+        }
+    }
+}
+
+/// Whether the frame is tagged with `KEEPALIVE_TAG`.
+fn is_keepalive(data: &str) -> bool {
+    #[derive(Deserialize)]
+    struct Tagged<'a> {
+        #[serde(rename = "customTag", borrow)]
+        custom_tag: Option<std::borrow::Cow<'a, str>>,
+    }
+
+    serde_json::from_str::<Tagged>(data)
+        .ok()
+        .and_then(|tagged| tagged.custom_tag)
+        .is_some_and(|tag| tag == KEEPALIVE_TAG)
+}
+
+/// Recorded session, which may be replayed with `ConnectionConfig::replay`.
+#[derive(Debug, Clone, Default)]
+pub struct Cassette {
+    frames: Vec<Frame>,
+}
+
+impl Cassette {
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Cassette> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads a cassette from JSON lines. Empty lines are skipped.
+    pub fn from_reader<R: BufRead>(reader: R) -> std::io::Result<Cassette> {
+        let mut frames = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                frames.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(Cassette { frames })
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Returns a transport feeding back the frames received on the channel.
+    pub(crate) fn transport(&self, channel: Channel) -> (Sink, Source) {
+        let frames: Vec<Frame> = self
+            .frames
+            .iter()
+            .filter(|frame| frame.channel == channel)
+            .cloned()
+            .collect();
+        let (sent, sent_count) = watch::channel(0usize);
+
+        let sink = futures::sink::unfold(sent, |sent, message: Message| async move {
+            if message.is_text() {
+                sent.send_modify(|count| *count += 1);
+            }
+            Ok::<_, WsError>(sent)
+        });
+
+        let source = futures::stream::unfold(
+            (frames.into_iter(), sent_count, 0usize),
+            |(mut frames, mut sent_count, mut expected)| async move {
+                loop {
+                    let frame = frames.next()?;
+                    match frame.direction {
+                        Direction::Sent => {
+                            expected += 1;
+                            sent_count.wait_for(|count| *count >= expected).await.ok()?;
+                        }
+                        Direction::Received => {
+                            let message = Ok(Message::Text(frame.data));
+                            return Some((message, (frames, sent_count, expected)));
+                        }
+                    }
+                }
+            },
+        );
+
+        (Box::pin(sink), source.boxed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConnectionConfig;
+    use crate::data::{KeepAlive, Record};
+    use crate::mock::MockServer;
+
+    use tokio::time::{timeout, Duration};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Application code, which runs the same way live and replayed.
+    async fn session(config: &ConnectionConfig, server: Option<&MockServer>) -> (String, i64) {
+        let credentials = match server {
+            Some(server) => server.credentials(),
+            None => Default::default(),
+        };
+        let x = crate::connect_with_config(&credentials, config).await.unwrap();
+        let symbol = x.socket.get_symbol("EURUSD").await.unwrap().return_data.symbol;
+
+        x.stream.get_keep_alive().await.unwrap();
+        if let Some(server) = server {
+            server.wait_for("getKeepAlive", Duration::from_secs(5)).await.unwrap();
+            server.push("keepAlive", KeepAlive { timestamp: 1234 });
+        }
+        let timestamp = match timeout(Duration::from_secs(5), x.stream.listen()).await.unwrap() {
+            Ok(Record::KeepAlive(keep_alive)) => keep_alive.timestamp,
+            other => panic!("unexpected record: {:?}", other),
+        };
+        (symbol, timestamp)
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let buffer = Buffer::default();
+        let server = MockServer::start().await.unwrap();
+        let config = ConnectionConfig {
+            recorder: Some(Recorder::new(buffer.clone())),
+            ..Default::default()
+        };
+        let recorded = session(&config, Some(&server)).await;
+        drop(server);
+
+        let cassette = Cassette::from_reader(buffer.0.lock().unwrap().as_slice()).unwrap();
+        let login = cassette
+            .frames()
+            .iter()
+            .find(|frame| frame.data.contains("\"login\""))
+            .unwrap();
+        assert_eq!(login.direction, Direction::Sent);
+        assert!(login.data.contains("\"password\":\"***\""));
+        assert!(cassette.frames().iter().any(|frame| frame.channel == Channel::Stream));
+
+        let config = ConnectionConfig { replay: Some(cassette), ..Default::default() };
+        let replayed = session(&config, None).await;
+        assert_eq!(replayed, recorded);
+        assert_eq!(replayed, (String::from("EURUSD"), 1234));
+    }
+
+    #[tokio::test]
+    async fn test_keepalive_is_not_recorded() {
+        let buffer = Buffer::default();
+        let server = MockServer::start().await.unwrap();
+        let config = ConnectionConfig {
+            recorder: Some(Recorder::new(buffer.clone())),
+            keepalive_interval: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let x = crate::connect_with_config(&server.credentials(), &config)
+            .await
+            .unwrap();
+        x.socket.get_symbol("EURUSD").await.unwrap();
+        server.wait_for("ping", Duration::from_secs(5)).await.unwrap();
+        x.socket.get_symbol("EURUSD").await.unwrap();
+        x.close().await.unwrap();
+        drop(server);
+
+        let cassette = Cassette::from_reader(buffer.0.lock().unwrap().as_slice()).unwrap();
+        assert!(!cassette.frames().iter().any(|frame| frame.data.contains(KEEPALIVE_TAG)));
+
+        let config = ConnectionConfig {
+            replay: Some(cassette),
+            keepalive_interval: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let x = crate::connect_with_config(&Default::default(), &config).await.unwrap();
+        for _ in 0..2 {
+            let symbol = timeout(Duration::from_secs(5), x.socket.get_symbol("EURUSD"))
+                .await
+                .unwrap();
+            assert_eq!(symbol.unwrap().return_data.symbol, "EURUSD");
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use serde::{Serialize, Serializer};
+
+/// Serializes a price or a volume rounded to 10 decimal places, so that the noise of `f64` arithmetic,
+/// e.g. `0.1 + 0.2 = 0.30000000000000004`, is not sent to the server.
+fn rounded<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+    let rounded = format!("{:.10}", value).parse().unwrap_or(*value);
+    serializer.serialize_f64(rounded)
+}
+
+/// Socket command envelope, e.g. `{"command":"getSymbol","arguments":{"symbol":"EURUSD"}}`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Command<'a, A = ()> {
+    pub command: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<A>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom_tag: Option<String>,
+    /// Symbol the command is about, for instrumentation only. Not sent.
+    #[serde(skip)]
+    pub symbol: Option<&'a str>,
+}
+
+impl<'a> Command<'a> {
+    pub fn new(command: &'a str) -> Command<'a> {
+        Command { command, arguments: None, custom_tag: None, symbol: None }
+    }
+}
+
+impl<'a, A: Serialize> Command<'a, A> {
+    pub fn with_arguments(command: &'a str, arguments: A) -> Command<'a, A> {
+        Command {
+            command,
+            arguments: Some(arguments),
+            custom_tag: None,
+            symbol: None,
+        }
+    }
+
+    pub fn with_symbol(mut self, symbol: &'a str) -> Command<'a, A> {
+        self.symbol = Some(symbol);
+        self
+    }
+}
+
+/// Stream command envelope. Stream commands carry their arguments at the top level,
+/// e.g. `{"command":"getCandles","streamSessionId":"...","symbol":"EURUSD"}`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamCommand<'a, A = ()> {
+    pub command: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stream_session_id: Option<&'a str>,
+    #[serde(flatten)]
+    pub arguments: A,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginArgs<'a> {
+    pub user_id: &'a str,
+    pub password: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InfoArgs<T> {
+    pub info: T,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartLastInfo<'a> {
+    pub period: i64,
+    pub start: i64,
+    pub symbol: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChartRangeInfo<'a> {
+    pub end: i64,
+    pub period: i64,
+    pub start: i64,
+    pub symbol: &'a str,
+    pub ticks: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolVolumeArgs<'a> {
+    pub symbol: &'a str,
+    #[serde(serialize_with = "rounded")]
+    pub volume: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeRangeArgs {
+    pub end: i64,
+    pub start: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProfitCalculationArgs<'a> {
+    #[serde(serialize_with = "rounded")]
+    pub close_price: f64,
+    pub cmd: i64,
+    #[serde(serialize_with = "rounded")]
+    pub open_price: f64,
+    pub symbol: &'a str,
+    #[serde(serialize_with = "rounded")]
+    pub volume: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolArgs<'a> {
+    pub symbol: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolsArgs<'a> {
+    pub symbols: &'a [&'a str],
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TickPricesArgs<'a> {
+    pub level: i64,
+    pub symbols: &'a [&'a str],
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrdersArgs<'a> {
+    pub orders: &'a [i64],
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenedOnlyArgs {
+    pub opened_only: bool,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderArgs {
+    pub order: i64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeTransactionArgs<'a> {
+    pub trade_trans_info: TradeTransInfo<'a>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TradeTransInfo<'a> {
+    pub cmd: i64,
+    pub custom_comment: &'a str,
+    pub expiration: i64,
+    pub offset: i64,
+    pub order: i64,
+    #[serde(serialize_with = "rounded")]
+    pub price: f64,
+    #[serde(serialize_with = "rounded")]
+    pub sl: f64,
+    pub symbol: &'a str,
+    #[serde(serialize_with = "rounded")]
+    pub tp: f64,
+    #[serde(rename = "type")]
+    pub type_: i64,
+    #[serde(serialize_with = "rounded")]
+    pub volume: f64,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TickPricesStreamArgs<'a> {
+    pub symbol: &'a str,
+    pub min_arrival_time: i64,
+    pub max_level: i64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::{json, Value};
+
+    fn wire<T: Serialize>(command: &T) -> String {
+        serde_json::to_string(command).unwrap()
+    }
+
+    fn round_trip<T: Serialize>(command: &T) -> Value {
+        serde_json::from_str(&wire(command)).unwrap()
+    }
+
+    #[test]
+    fn test_command_without_arguments() {
+        assert_eq!(wire(&Command::new("getVersion")), r#"{"command":"getVersion"}"#);
+        assert_eq!(wire(&Command::new("ping")), r#"{"command":"ping"}"#);
+    }
+
+    #[test]
+    fn test_command_with_custom_tag() {
+        let mut command = Command::new("getVersion");
+        command.custom_tag = Some(String::from("7"));
+        assert_eq!(wire(&command), r#"{"command":"getVersion","customTag":"7"}"#);
+    }
+
+    #[test]
+    fn test_login() {
+        let command = Command::with_arguments("login", LoginArgs { user_id: "1000", password: "pa\"ss\\word" });
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"login","arguments":{"userId":"1000","password":"pa\"ss\\word"}}"#
+        );
+        assert_eq!(round_trip(&command)["arguments"]["password"], "pa\"ss\\word");
+    }
+
+    #[test]
+    fn test_chart_last_request() {
+        let info = ChartLastInfo { period: 5, start: 1000, symbol: "EURUSD" };
+        let command = Command::with_arguments("getChartLastRequest", InfoArgs { info });
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getChartLastRequest","arguments":{"info":{"period":5,"start":1000,"symbol":"EURUSD"}}}"#
+        );
+    }
+
+    #[test]
+    fn test_chart_range_request() {
+        let info = ChartRangeInfo {
+            end: 2000,
+            period: 60,
+            start: 1000,
+            symbol: "EURUSD",
+            ticks: 0,
+        };
+        let command = Command::with_arguments("getChartRangeRequest", InfoArgs { info });
+        assert_eq!(
+            round_trip(&command),
+            json!({
+                "command": "getChartRangeRequest",
+                "arguments": { "info": { "end": 2000, "period": 60, "start": 1000, "symbol": "EURUSD", "ticks": 0 } }
+            })
+        );
+    }
+
+    #[test]
+    fn test_symbol_volume() {
+        let command = Command::with_arguments("getCommissionDef", SymbolVolumeArgs { symbol: "EURUSD", volume: 0.1 });
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getCommissionDef","arguments":{"symbol":"EURUSD","volume":0.1}}"#
+        );
+
+        let command = Command::with_arguments("getMarginTrade", SymbolVolumeArgs { symbol: "GOLD", volume: 2.5 });
+        assert_eq!(
+            round_trip(&command),
+            json!({ "command": "getMarginTrade", "arguments": { "symbol": "GOLD", "volume": 2.5 } })
+        );
+    }
+
+    #[test]
+    fn test_rounded_prices_and_volumes() {
+        let noisy = 0.1 + 0.2;
+        let command = Command::with_arguments("getMarginTrade", SymbolVolumeArgs { symbol: "GOLD", volume: noisy });
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getMarginTrade","arguments":{"symbol":"GOLD","volume":0.3}}"#
+        );
+
+        let args = ProfitCalculationArgs {
+            close_price: 1.0 + 0.085 + 1e-12,
+            cmd: 0,
+            open_price: noisy,
+            symbol: "EURUSD",
+            volume: noisy,
+        };
+        let command = Command::with_arguments("getProfitCalculation", args);
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getProfitCalculation","arguments":{"closePrice":1.085,"cmd":0,"openPrice":0.3,"symbol":"EURUSD","volume":0.3}}"#
+        );
+
+        let trade_trans_info = TradeTransInfo {
+            cmd: 0,
+            custom_comment: "",
+            expiration: 0,
+            offset: 0,
+            order: 0,
+            price: noisy,
+            sl: noisy,
+            tp: noisy,
+            symbol: "EURUSD",
+            type_: 0,
+            volume: noisy,
+        };
+        let command = Command::with_arguments("tradeTransaction", TradeTransactionArgs { trade_trans_info });
+        let info = &round_trip(&command)["arguments"]["tradeTransInfo"];
+        for field in ["price", "sl", "tp", "volume"] {
+            assert_eq!(info[field].to_string(), "0.3");
+        }
+    }
+
+    #[test]
+    fn test_time_range() {
+        for name in ["getIbsHistory", "getNews", "getTradesHistory"] {
+            let command = Command::with_arguments(name, TimeRangeArgs { end: 0, start: 1000 });
+            assert_eq!(
+                round_trip(&command),
+                json!({ "command": name, "arguments": { "end": 0, "start": 1000 } })
+            );
+        }
+    }
+
+    #[test]
+    fn test_profit_calculation() {
+        let args = ProfitCalculationArgs {
+            close_price: 1.3,
+            cmd: 0,
+            open_price: 1.2233,
+            symbol: "EURPLN",
+            volume: 1.0,
+        };
+        let command = Command::with_arguments("getProfitCalculation", args);
+        assert_eq!(
+            round_trip(&command),
+            json!({
+                "command": "getProfitCalculation",
+                "arguments": { "closePrice": 1.3, "cmd": 0, "openPrice": 1.2233, "symbol": "EURPLN", "volume": 1.0 }
+            })
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        let command = Command::with_arguments("getSymbol", SymbolArgs { symbol: "EUR\"USD" });
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getSymbol","arguments":{"symbol":"EUR\"USD"}}"#
+        );
+
+        let command = Command::with_arguments("getTradingHours", SymbolsArgs { symbols: &["EURUSD", "GOLD"] });
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getTradingHours","arguments":{"symbols":["EURUSD","GOLD"]}}"#
+        );
+
+        let args = TickPricesArgs { level: 0, symbols: &["EURUSD"], timestamp: 1000 };
+        let command = Command::with_arguments("getTickPrices", args);
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getTickPrices","arguments":{"level":0,"symbols":["EURUSD"],"timestamp":1000}}"#
+        );
+    }
+
+    #[test]
+    fn test_orders() {
+        let command = Command::with_arguments("getTradeRecords", OrdersArgs { orders: &[7489839, 7489841] });
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getTradeRecords","arguments":{"orders":[7489839,7489841]}}"#
+        );
+
+        let command = Command::with_arguments("getTrades", OpenedOnlyArgs { opened_only: true });
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getTrades","arguments":{"openedOnly":true}}"#
+        );
+
+        let command = Command::with_arguments("tradeTransactionStatus", OrderArgs { order: 43 });
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"tradeTransactionStatus","arguments":{"order":43}}"#
+        );
+    }
+
+    #[test]
+    fn test_trade_transaction() {
+        let trade_trans_info = TradeTransInfo {
+            cmd: 2,
+            custom_comment: "\",\"volume\":100",
+            expiration: 1462006335000,
+            offset: 0,
+            order: 82188055,
+            price: 1.12,
+            sl: 0.0,
+            symbol: "EURUSD",
+            tp: 0.0,
+            type_: 0,
+            volume: 5.0,
+        };
+        let command = Command::with_arguments("tradeTransaction", TradeTransactionArgs { trade_trans_info });
+        assert_eq!(
+            round_trip(&command),
+            json!({
+                "command": "tradeTransaction",
+                "arguments": {
+                    "tradeTransInfo": {
+                        "cmd": 2,
+                        "customComment": "\",\"volume\":100",
+                        "expiration": 1462006335000i64,
+                        "offset": 0,
+                        "order": 82188055,
+                        "price": 1.12,
+                        "sl": 0.0,
+                        "symbol": "EURUSD",
+                        "tp": 0.0,
+                        "type": 0,
+                        "volume": 5.0
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_stream_commands() {
+        let command = StreamCommand {
+            command: "getBalance",
+            stream_session_id: Some("8469308861804289383"),
+            arguments: (),
+        };
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getBalance","streamSessionId":"8469308861804289383"}"#
+        );
+
+        let command = StreamCommand {
+            command: "stopBalance",
+            stream_session_id: None,
+            arguments: (),
+        };
+        assert_eq!(wire(&command), r#"{"command":"stopBalance"}"#);
+
+        let command = StreamCommand {
+            command: "getCandles",
+            stream_session_id: Some("8469308861804289383"),
+            arguments: SymbolArgs { symbol: "EURUSD" },
+        };
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getCandles","streamSessionId":"8469308861804289383","symbol":"EURUSD"}"#
+        );
+
+        let command = StreamCommand {
+            command: "getTickPrices",
+            stream_session_id: Some("8469308861804289383"),
+            arguments: TickPricesStreamArgs { symbol: "EURUSD", min_arrival_time: 5000, max_level: 2 },
+        };
+        assert_eq!(
+            wire(&command),
+            r#"{"command":"getTickPrices","streamSessionId":"8469308861804289383","symbol":"EURUSD","minArrivalTime":5000,"maxLevel":2}"#
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use crate::cassette::{Cassette, Recorder};
+use crate::credentials::Credentials;
+use crate::error::Error;
+use crate::limiter::{RateLimiter, RateLimiterConfig};
+use crate::metrics::Metrics;
+use crate::proxy::Proxy;
+use crate::tls::TlsConfig;
+use crate::XApi;
+
+use std::sync::Arc;
+use tokio::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+
+/// Connection settings. The defaults match the behavior of `xapi::connect`.
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    /// Interval of WebSocket Ping frames.
+    pub ping_interval: Duration,
+    /// Interval of xAPI `ping` commands, keeping the session alive. `None` disables them.
+    /// A ping is sent only if no other request was sent for the interval. Disabled when replaying.
+    pub keepalive_interval: Option<Duration>,
+    /// Maximal time without any incoming frame, before the connection is considered dead.
+    pub read_timeout: Duration,
+    /// Maximal gap between `KeepAlive` stream records, before the connection is considered degraded.
+    /// Applies once the first `KeepAlive` record arrived, see `Stream::get_keep_alive`.
+    pub heartbeat_timeout: Duration,
+    /// Whether the stream subscribes to `KeepAlive` records and fails with `Error::StaleConnection`,
+    /// when none arrives for `heartbeat_timeout`. A `Supervisor` reconnects on such a failure.
+    pub heartbeat_watchdog: bool,
+    /// Maximal time to wait for a response to a socket command, before failing with `Error::RequestTimeout`.
+    /// No limit by default, a dead connection is detected by `read_timeout`.
+    pub request_timeout: Option<Duration>,
+    /// Pacing of outgoing requests, used when no shared `limiter` is given.
+    pub rate_limit: RateLimiterConfig,
+    /// Rate limiter shared by all connections made with this config.
+    pub limiter: Option<RateLimiter>,
+    /// Socket url. Defaults to `{host}/{type}`.
+    pub socket_url: Option<String>,
+    /// Stream url. Defaults to `{host}/{type}Stream`.
+    pub stream_url: Option<String>,
+    /// Number of stream records buffered for each record receiver.
+    pub stream_capacity: usize,
+    /// Maximal size of an incoming message. `None` means no limit.
+    pub max_message_size: Option<usize>,
+    /// Maximal size of an incoming frame. `None` means no limit.
+    pub max_frame_size: Option<usize>,
+    /// Proxy used by both the socket and the stream connection.
+    pub proxy: Option<Proxy>,
+    /// TLS settings, e.g. custom root certificates.
+    pub tls: TlsConfig,
+    /// Recorder of all frames sent and received, see `Recorder`.
+    pub recorder: Option<Recorder>,
+    /// Cassette replayed instead of connecting to the server.
+    pub replay: Option<Cassette>,
+    /// Instrumentation hooks, see `Metrics`.
+    pub metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        let websocket = WebSocketConfig::default();
+        Self {
+            ping_interval: Duration::from_secs(5),
+            keepalive_interval: None,
+            read_timeout: Duration::from_secs(15),
+            heartbeat_timeout: Duration::from_secs(10),
+            heartbeat_watchdog: false,
+            request_timeout: None,
+            rate_limit: RateLimiterConfig::default(),
+            limiter: None,
+            socket_url: None,
+            stream_url: None,
+            stream_capacity: 1024,
+            max_message_size: websocket.max_message_size,
+            max_frame_size: websocket.max_frame_size,
+            proxy: None,
+            tls: TlsConfig::default(),
+            recorder: None,
+            replay: None,
+            metrics: None,
+        }
+    }
+}
+
+impl ConnectionConfig {
+    /// Returns the socket and the stream urls for the given credentials.
+    pub fn urls(&self, credentials: &Credentials) -> (String, String) {
+        let mut host = credentials.host.clone();
+        if !host.starts_with("wss://") && !host.starts_with("ws://") {
+            host.insert_str(0, "wss://");
+        }
+
+        let socket_url = match &self.socket_url {
+            Some(url) => url.clone(),
+            None => format!("{}/{}", host, credentials.type_),
+        };
+        let stream_url = match &self.stream_url {
+            Some(url) => url.clone(),
+            None => format!("{}/{}Stream", host, credentials.type_),
+        };
+        (socket_url, stream_url)
+    }
+
+    pub(crate) fn limiter(&self) -> RateLimiter {
+        match &self.limiter {
+            Some(limiter) => limiter.clone(),
+            None => RateLimiter::new(self.rate_limit),
+        }
+    }
+
+    /// Returns the keepalive interval, unless replaying a cassette.
+    pub(crate) fn keepalive(&self) -> Option<Duration> {
+        match self.replay {
+            Some(_) => None,
+            None => self.keepalive_interval,
+        }
+    }
+
+    pub(crate) fn websocket_config(&self) -> WebSocketConfig {
+        WebSocketConfig {
+            max_message_size: self.max_message_size,
+            max_frame_size: self.max_frame_size,
+            ..Default::default()
+        }
+    }
+}
+
+/// Builder of `XApi` connections, e.g.
+/// `XApiBuilder::new(&credentials).ping_interval(Duration::from_secs(10)).connect().await?`.
+#[derive(Debug, Clone)]
+pub struct XApiBuilder {
+    credentials: Credentials,
+    config: ConnectionConfig,
+}
+
+impl XApiBuilder {
+    pub fn new(credentials: &Credentials) -> XApiBuilder {
+        XApiBuilder {
+            credentials: credentials.clone(),
+            config: ConnectionConfig::default(),
+        }
+    }
+
+    pub fn config(mut self, config: ConnectionConfig) -> XApiBuilder {
+        self.config = config;
+        self
+    }
+
+    pub fn ping_interval(mut self, interval: Duration) -> XApiBuilder {
+        self.config.ping_interval = interval;
+        self
+    }
+
+    pub fn keepalive(mut self, interval: Duration) -> XApiBuilder {
+        self.config.keepalive_interval = Some(interval);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> XApiBuilder {
+        self.config.read_timeout = timeout;
+        self
+    }
+
+    pub fn heartbeat_timeout(mut self, timeout: Duration) -> XApiBuilder {
+        self.config.heartbeat_timeout = timeout;
+        self
+    }
+
+    /// Enables the heartbeat watchdog with the given timeout, see `ConnectionConfig::heartbeat_watchdog`.
+    pub fn heartbeat_watchdog(mut self, timeout: Duration) -> XApiBuilder {
+        self.config.heartbeat_watchdog = true;
+        self.config.heartbeat_timeout = timeout;
+        self
+    }
+
+    pub fn request_timeout(mut self, timeout: Duration) -> XApiBuilder {
+        self.config.request_timeout = Some(timeout);
+        self
+    }
+
+    pub fn pacing_interval(mut self, interval: Duration) -> XApiBuilder {
+        self.config.rate_limit.interval = interval;
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: RateLimiterConfig) -> XApiBuilder {
+        self.config.rate_limit = rate_limit;
+        self
+    }
+
+    pub fn limiter(mut self, limiter: RateLimiter) -> XApiBuilder {
+        self.config.limiter = Some(limiter);
+        self
+    }
+
+    pub fn socket_url(mut self, url: &str) -> XApiBuilder {
+        self.config.socket_url = Some(String::from(url));
+        self
+    }
+
+    pub fn stream_url(mut self, url: &str) -> XApiBuilder {
+        self.config.stream_url = Some(String::from(url));
+        self
+    }
+
+    pub fn stream_capacity(mut self, capacity: usize) -> XApiBuilder {
+        self.config.stream_capacity = capacity;
+        self
+    }
+
+    pub fn max_message_size(mut self, size: Option<usize>) -> XApiBuilder {
+        self.config.max_message_size = size;
+        self
+    }
+
+    pub fn max_frame_size(mut self, size: Option<usize>) -> XApiBuilder {
+        self.config.max_frame_size = size;
+        self
+    }
+
+    pub fn proxy(mut self, proxy: Proxy) -> XApiBuilder {
+        self.config.proxy = Some(proxy);
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsConfig) -> XApiBuilder {
+        self.config.tls = tls;
+        self
+    }
+
+    /// Trusts the PEM encoded certificate, in addition to the default roots.
+    pub fn root_certificate(mut self, pem: &[u8]) -> XApiBuilder {
+        match &mut self.config.tls {
+            TlsConfig::RootCertificates(certificates) => certificates.push(pem.to_vec()),
+            tls => *tls = TlsConfig::RootCertificates(vec![pem.to_vec()]),
+        }
+        self
+    }
+
+    pub fn record(mut self, recorder: Recorder) -> XApiBuilder {
+        self.config.recorder = Some(recorder);
+        self
+    }
+
+    pub fn replay(mut self, cassette: Cassette) -> XApiBuilder {
+        self.config.replay = Some(cassette);
+        self
+    }
+
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> XApiBuilder {
+        self.config.metrics = Some(metrics);
+        self
+    }
+
+    pub async fn connect(self) -> Result<XApi, Error> {
+        crate::connect_with_config(&self.credentials, &self.config).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_urls() {
+        let credentials = Credentials {
+            host: String::from("ws.xtb.com"),
+            type_: String::from("demo"),
+            ..Default::default()
+        };
+        let (socket_url, stream_url) = ConnectionConfig::default().urls(&credentials);
+        assert_eq!(socket_url, "wss://ws.xtb.com/demo");
+        assert_eq!(stream_url, "wss://ws.xtb.com/demoStream");
+
+        let credentials = Credentials { host: String::from("ws://localhost:8080"), ..credentials };
+        let (socket_url, _) = ConnectionConfig::default().urls(&credentials);
+        assert_eq!(socket_url, "ws://localhost:8080/demo");
+    }
+
+    #[test]
+    fn test_explicit_urls() {
+        let config = ConnectionConfig {
+            socket_url: Some(String::from("wss://example.com/socket")),
+            stream_url: Some(String::from("wss://example.com/stream")),
+            ..Default::default()
+        };
+        let (socket_url, stream_url) = config.urls(&Credentials::default());
+        assert_eq!(socket_url, "wss://example.com/socket");
+        assert_eq!(stream_url, "wss://example.com/stream");
+    }
+
+    #[tokio::test]
+    async fn test_builder_connects() {
+        let server = crate::mock::MockServer::start().await.unwrap();
+        let x = XApiBuilder::new(&server.credentials())
+            .ping_interval(Duration::from_secs(1))
+            .request_timeout(Duration::from_secs(1))
+            .pacing_interval(Duration::from_millis(50))
+            .connect()
+            .await
+            .unwrap();
+        assert!(x.socket.ping().await.is_ok());
+        assert_eq!(x.socket.limiter().config().interval, Duration::from_millis(50));
+    }
+}
@@ -1,57 +1,193 @@
+use crate::cassette::{Channel, Direction, Recorder};
+use crate::command::Command;
+use crate::config::ConnectionConfig;
 use crate::data::ErrorResponse;
 use crate::error::Error;
+use crate::limiter::RateLimiter;
+use crate::metrics::Metrics;
+use crate::redact::redact;
+use crate::state::Health;
 
+use futures::stream::BoxStream;
 use futures::{SinkExt, StreamExt};
-use futures_util::stream::{SplitSink, SplitStream};
 use log::*;
 use serde::de::DeserializeOwned;
-use std::ops::Deref;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
 
 use tokio::net::TcpStream;
-use tokio::sync::Mutex;
-use tokio::time::{sleep, timeout, Duration};
+use tokio::sync::{oneshot, watch, Mutex};
+use tokio::time::{sleep, sleep_until, timeout, Duration, Instant};
 
+use tokio_tungstenite::tungstenite::error::UrlError;
+use tokio_tungstenite::tungstenite::http::Uri;
 use tokio_tungstenite::tungstenite::protocol::Message;
-use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite::Error as WsError;
 
-const PING_INTERVAL: Duration = Duration::from_secs(5);
+/// Outgoing half of a transport: a WebSocket connection, or a replayed cassette.
+pub(crate) type Sink = Pin<Box<dyn futures::Sink<Message, Error = WsError> + Send>>;
+/// Incoming half of a transport.
+pub(crate) type Source = BoxStream<'static, Result<Message, WsError>>;
 
-#[derive(Debug, Clone)]
+/// `customTag` of keepalive pings. Their responses are not routed to any caller.
+pub const KEEPALIVE_TAG: &str = "keepalive";
+
+/// Callers waiting for a response, by the `customTag` of their request. `None` once the dispatcher has stopped.
+type Pending = Arc<PendingMap>;
+type PendingMap = std::sync::Mutex<Option<BTreeMap<u64, oneshot::Sender<Result<String, Error>>>>>;
+
+#[derive(Clone)]
 pub struct Connection {
-    write: Arc<Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>,
-    read: Arc<Mutex<SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>>>,
-    last_request_time: Arc<Mutex<Option<Instant>>>,
-    transaction: Arc<Mutex<()>>,
+    write: Arc<Mutex<Sink>>,
+    read: Arc<Mutex<Source>>,
+    limiter: RateLimiter,
+    pending: Pending,
+    next_tag: Arc<AtomicU64>,
+    read_timeout: Duration,
+    request_timeout: Option<Duration>,
+    closed: Arc<watch::Sender<bool>>,
+    last_sent: Arc<std::sync::Mutex<Instant>>,
+    health: Arc<watch::Sender<Health>>,
+    channel: Channel,
+    recorder: Option<Recorder>,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connection")
+            .field("channel", &self.channel)
+            .field("limiter", &self.limiter)
+            .field("closed", &self.is_closed())
+            .finish_non_exhaustive()
+    }
 }
 
 impl Connection {
-    pub async fn connect(url: &str) -> Result<Connection, Error> {
-        let (ws_stream, _) = tokio_tungstenite::connect_async(url).await?;
-        let (write, read) = ws_stream.split();
+    pub async fn connect(url: &str, config: &ConnectionConfig, channel: Channel) -> Result<Connection, Error> {
+        let (write, read) = match &config.replay {
+            Some(cassette) => cassette.transport(channel),
+            None => Self::open(url, config).await?,
+        };
 
         let conn = Connection {
             write: Arc::new(Mutex::new(write)),
             read: Arc::new(Mutex::new(read)),
-            last_request_time: Arc::new(Mutex::new(None)),
-            transaction: Arc::new(Mutex::new(())),
+            limiter: config.limiter(),
+            pending: Arc::new(std::sync::Mutex::new(Some(BTreeMap::new()))),
+            next_tag: Arc::new(AtomicU64::new(1)),
+            read_timeout: config.read_timeout,
+            request_timeout: config.request_timeout,
+            closed: Arc::new(watch::channel(false).0),
+            last_sent: Arc::new(std::sync::Mutex::new(Instant::now())),
+            health: Arc::new(watch::channel(Health::Healthy).0),
+            channel,
+            recorder: config.recorder.clone(),
+            metrics: config.metrics.clone(),
         };
 
-        conn.spawn_pinging_task();
+        conn.spawn_pinging_task(config.ping_interval);
 
         Ok(conn)
     }
 
+    async fn open(url: &str, config: &ConnectionConfig) -> Result<(Sink, Source), Error> {
+        let (host, port) = target(url)?;
+        let tcp = match &config.proxy {
+            Some(proxy) => proxy.connect(&host, port).await?,
+            None => TcpStream::connect((host.as_str(), port)).await.map_err(io_error)?,
+        };
+        tcp.set_nodelay(true).map_err(io_error)?;
+
+        #[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+        let (ws_stream, _) = {
+            let connector = config.tls.connector()?;
+            tokio_tungstenite::client_async_tls_with_config(url, tcp, Some(config.websocket_config()), connector)
+                .await?
+        };
+        // Never built, the crate requires a TLS backend. Keeps the `compile_error!` of lib.rs the only error.
+        #[cfg(not(any(feature = "native-tls", feature = "rustls-tls")))]
+        let (ws_stream, _) =
+            tokio_tungstenite::client_async_with_config(url, tcp, Some(config.websocket_config())).await?;
+        let (write, read) = ws_stream.split();
+        Ok((Box::pin(write), read.boxed()))
+    }
+
     pub async fn skip_delay(&self) {
-        let mut last_request_time = self.last_request_time.lock().await;
-        *last_request_time = None;
+        self.limiter.skip().await;
+    }
+
+    pub fn limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    pub fn metrics(&self) -> Option<&dyn Metrics> {
+        self.metrics.as_deref()
     }
 
-    pub async fn transaction<T: DeserializeOwned>(&self, command: &str) -> Result<T, Error> {
-        let _transaction = self.transaction.lock().await;
-        self.request(command).await?;
-        let response = self.receive().await?;
+    /// Sends the command and waits for its response. The command is tagged with a unique `customTag`,
+    /// which the server echoes back, so many transactions may be in flight at once.
+    /// Requires the dispatching task, see `spawn_dispatching_task`.
+    pub async fn transaction<A: Serialize, T: DeserializeOwned>(&self, command: Command<'_, A>) -> Result<T, Error> {
+        let name = command.command;
+
+        #[cfg(feature = "tracing")]
+        let result = {
+            use tracing::{field, Instrument};
+            let symbol = command.symbol;
+            let span = tracing::info_span!("xapi", command = name, symbol, outcome = field::Empty);
+            let result = self.exchange(command).instrument(span.clone()).await;
+            match &result {
+                Ok(_) => span.record("outcome", "ok"),
+                Err(err) => span.record("outcome", err.kind()),
+            };
+            result
+        };
+        #[cfg(not(feature = "tracing"))]
+        let result = self.exchange(command).await;
+
+        if let (Some(metrics), Err(err)) = (self.metrics(), &result) {
+            metrics.request_error(name, err);
+        }
+        result
+    }
+
+    async fn exchange<A: Serialize, T: DeserializeOwned>(&self, mut command: Command<'_, A>) -> Result<T, Error> {
+        let name = command.command;
+        let tag = self.next_tag.fetch_add(1, Ordering::Relaxed);
+        command.custom_tag = Some(tag.to_string());
+        let command = serde_json::to_string(&command)?;
+
+        let (sender, receiver) = oneshot::channel();
+        match self.pending.lock().unwrap().as_mut() {
+            Some(pending) => pending.insert(tag, sender),
+            None => return Err(Error::ConnectionClosed),
+        };
+
+        if let Err(err) = self.request(&command).await {
+            self.forget(tag);
+            return Err(err);
+        }
+
+        let sent = Instant::now();
+        let response = match Self::wait_for(self.request_timeout, receiver).await {
+            Ok(Ok(response)) => {
+                let response = response?;
+                if let Some(metrics) = self.metrics() {
+                    metrics.request_latency(name, sent.elapsed());
+                }
+                response
+            }
+            Ok(Err(_)) => return Err(Error::ConnectionClosed),
+            Err(_) => {
+                self.forget(tag);
+                return Err(Error::RequestTimeout);
+            }
+        };
 
         if let Ok(response) = serde_json::from_str::<ErrorResponse>(&response) {
             return Err(Error::ErrorResponse { response });
@@ -61,32 +197,65 @@ impl Connection {
         Ok(response)
     }
 
-    pub async fn request(&self, command: &str) -> Result<(), Error> {
-        let mut last_request_time = self.last_request_time.lock().await;
-        if let Some(last_request_time) = last_request_time.deref() {
-            let elapsed = last_request_time.elapsed();
-            if elapsed < Duration::from_millis(200) {
-                sleep(Duration::from_millis(200) - elapsed).await;
-            }
+    /// Awaits the response, for at most `request_timeout` when one is set.
+    async fn wait_for<F: std::future::Future>(
+        request_timeout: Option<Duration>,
+        response: F,
+    ) -> Result<F::Output, tokio::time::error::Elapsed> {
+        match request_timeout {
+            Some(request_timeout) => timeout(request_timeout, response).await,
+            None => Ok(response.await),
         }
+    }
 
+    pub async fn request(&self, command: &str) -> Result<(), Error> {
+        if self.is_closed() {
+            return Err(Error::ConnectionClosed);
+        }
+        // The writer is locked first, so that the request is sent as soon as the limiter lets it through.
         let mut write = self.write.lock().await;
+        let wait = self.limiter.acquire().await;
+        if let (Some(metrics), false) = (self.metrics(), wait.is_zero()) {
+            metrics.throttled(wait);
+        }
+        #[cfg(feature = "tracing")]
+        if !wait.is_zero() {
+            tracing::debug!(wait_ms = wait.as_millis() as u64, "Throttled by rate limiter");
+        }
+
+        if self.is_closed() {
+            return Err(Error::ConnectionClosed); // closed while waiting for the limiter
+        }
+        // Recorded before sending, so that the reply cannot be recorded first.
+        if let Some(recorder) = &self.recorder {
+            recorder.record(self.channel, Direction::Sent, command);
+        }
         write.send(Message::Text(String::from(command))).await?;
-        *last_request_time = Some(Instant::now());
-        debug!("Sent: {:?}", command);
+        *self.last_sent.lock().unwrap() = Instant::now();
+        debug!("Sent: {:?}", redact(command));
 
         Ok(())
     }
 
-    pub async fn receive(&self) -> Result<String, Error> {
-        let mut read = self.read.lock().await;
+    async fn receive_from(
+        read: &Mutex<Source>,
+        read_timeout: Duration,
+        mut closed: watch::Receiver<bool>,
+    ) -> Result<String, Error> {
+        let mut read = read.lock().await;
         loop {
-            let message = match timeout(PING_INTERVAL * 3, read.next()).await {
-                Ok(message) => message,
-                Err(_) => return Err(Error::ConnectionTimeout),
+            let message = tokio::select! {
+                message = timeout(read_timeout, read.next()) => match message {
+                    Ok(message) => message,
+                    Err(_) => return Err(Error::ConnectionTimeout),
+                },
+                _ = closed.wait_for(|closed| *closed) => return Err(Error::ConnectionClosed),
             };
 
-            debug!("Received: {:?}", message);
+            match &message {
+                Some(Ok(Message::Text(text))) => debug!("Received: Text({:?})", redact(text)),
+                message => debug!("Received: {:?}", message),
+            }
             match message {
                 Some(Ok(Message::Text(string))) => return Ok(string),
                 Some(Ok(Message::Binary(_))) | Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
@@ -97,17 +266,473 @@ impl Connection {
         }
     }
 
-    fn spawn_pinging_task(&self) {
+    fn spawn_pinging_task(&self, ping_interval: Duration) {
         let write = Arc::downgrade(&self.write);
+        let health = Arc::downgrade(&self.health);
+        let mut closed = self.closed.subscribe();
         tokio::spawn(async move {
             while let Some(write) = write.upgrade() {
                 let mut write = write.lock().await;
-                write.send(Message::Ping(Vec::new())).await.ok();
-                debug!("Sent: Ping([])");
+                match write.send(Message::Ping(Vec::new())).await {
+                    Ok(()) => debug!("Sent: Ping([])"),
+                    Err(err) => {
+                        debug!("Failed to send Ping: {}", err);
+                        degrade(&health, &closed);
+                    }
+                }
 
                 drop(write); // unlock write object, before sleep
-                sleep(PING_INTERVAL).await;
+                tokio::select! {
+                    _ = sleep(ping_interval) => {}
+                    _ = closed.wait_for(|closed| *closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Spawns a task, which sends the ping command whenever no request was sent for the interval.
+    /// Pings are sent with `request`, like any other command.
+    pub fn spawn_keepalive_task(&self, interval: Duration, ping: String) {
+        let conn = self.downgrade();
+        let last_sent = self.last_sent.clone();
+        let mut closed = self.closed.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let due = *last_sent.lock().unwrap() + interval;
+                tokio::select! {
+                    _ = sleep_until(due) => {}
+                    _ = closed.wait_for(|closed| *closed) => break,
+                }
+                if last_sent.lock().unwrap().elapsed() < interval {
+                    continue; // a request was sent in the meantime
+                }
+
+                let Some(conn) = conn.upgrade() else {
+                    break;
+                };
+                if let Err(err) = conn.request(&ping).await {
+                    debug!("Failed to send keepalive ping: {}", err);
+                    degrade(&Arc::downgrade(&conn.health), &closed);
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Returns a handle, which does not keep the connection alive.
+    fn downgrade(&self) -> WeakConnection {
+        WeakConnection {
+            write: Arc::downgrade(&self.write),
+            read: Arc::downgrade(&self.read),
+            limiter: self.limiter.clone(),
+            pending: Arc::downgrade(&self.pending),
+            next_tag: Arc::downgrade(&self.next_tag),
+            read_timeout: self.read_timeout,
+            request_timeout: self.request_timeout,
+            closed: Arc::downgrade(&self.closed),
+            last_sent: Arc::downgrade(&self.last_sent),
+            health: Arc::downgrade(&self.health),
+            channel: self.channel,
+            recorder: self.recorder.clone(),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        *self.closed.borrow()
+    }
+
+    pub fn health(&self) -> watch::Receiver<Health> {
+        self.health.subscribe()
+    }
+
+    /// Returns a tripwire, which stops the connection without keeping it alive.
+    pub fn tripwire(&self) -> Tripwire {
+        Tripwire {
+            closed: Arc::downgrade(&self.closed),
+            health: Arc::downgrade(&self.health),
+        }
+    }
+
+    /// Closes the connection with a Close frame and stops the background tasks.
+    /// Pending and further requests fail with `Error::ConnectionClosed`.
+    pub async fn close(&self) {
+        if self.closed.send_replace(true) {
+            return; // already closed
+        }
+        self.health.send_replace(Health::Closed);
+
+        let waiting = self.pending.lock().unwrap().take().unwrap_or_default();
+        for (_, sender) in waiting {
+            sender.send(Err(Error::ConnectionClosed)).ok();
+        }
+
+        let mut write = self.write.lock().await;
+        if let Err(err) = write.send(Message::Close(None)).await {
+            debug!("Failed to send Close frame: {}", err);
+        }
+        write.close().await.ok();
+        debug!("Sent: Close(None)");
+    }
+
+    fn forget(&self, tag: u64) {
+        if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+            pending.remove(&tag);
+        }
+    }
+
+    /// Spawns a task, which passes every incoming message to the handler,
+    /// until the handler breaks or receiving fails.
+    pub fn spawn_reading_task<F>(&self, mut handle: F)
+    where
+        F: FnMut(Result<String, Error>) -> ControlFlow<()> + Send + 'static,
+    {
+        let read = self.read.clone();
+        let read_timeout = self.read_timeout;
+        let closed = self.closed.subscribe();
+        let recorder = self.recorder.clone().map(|recorder| (recorder, self.channel));
+        let metrics = self.metrics.clone();
+        let health = Arc::downgrade(&self.health);
+        tokio::spawn(async move {
+            loop {
+                let message = Self::receive_from(&read, read_timeout, closed.clone()).await;
+                match (&message, &recorder, &metrics) {
+                    (Ok(message), Some((recorder, channel)), _) => {
+                        recorder.record(*channel, Direction::Received, message);
+                    }
+                    (Err(err), _, Some(metrics)) if err.is_connection_error() && !*closed.borrow() => {
+                        metrics.connection_error(err);
+                    }
+                    _ => {}
+                }
+                if message.is_err() {
+                    degrade(&health, &closed);
+                }
+                let failed = message.is_err();
+                if handle(message).is_break() || failed {
+                    break;
+                }
             }
         });
     }
+
+    /// Spawns a task, which reads all incoming messages and routes them to the transactions waiting for them,
+    /// by their `customTag`. A response without a tag is routed to the oldest waiting transaction.
+    pub fn spawn_dispatching_task(&self) {
+        #[derive(Deserialize)]
+        struct Tagged {
+            #[serde(rename = "customTag")]
+            custom_tag: Option<String>,
+        }
+
+        let pending = Arc::downgrade(&self.pending);
+        self.spawn_reading_task(move |response| {
+            let Some(pending) = pending.upgrade() else {
+                return ControlFlow::Break(());
+            };
+            let mut pending = pending.lock().unwrap();
+
+            let response = match response {
+                Ok(response) => response,
+                Err(err) => {
+                    debug!("Dispatching stopped: {}", err);
+                    for (_, sender) in pending.take().unwrap_or_default() {
+                        sender.send(Err(err.replicate())).ok();
+                    }
+                    return ControlFlow::Break(());
+                }
+            };
+
+            let Some(pending) = pending.as_mut() else {
+                return ControlFlow::Break(());
+            };
+            let tag = serde_json::from_str::<Tagged>(&response)
+                .ok()
+                .and_then(|tagged| tagged.custom_tag);
+            if tag.as_deref() == Some(KEEPALIVE_TAG) {
+                return ControlFlow::Continue(());
+            }
+            let tag = tag.and_then(|tag| tag.parse::<u64>().ok());
+            let sender = match tag {
+                Some(tag) => pending.remove(&tag),
+                None => pending.pop_first().map(|(_, sender)| sender),
+            };
+            match sender {
+                Some(sender) => {
+                    sender.send(Ok(response)).ok();
+                }
+                None => warn!("Dropped unexpected response: {:?}", redact(&response)),
+            }
+            ControlFlow::Continue(())
+        });
+    }
+}
+
+/// Connection held by a background task, see `Connection::downgrade`.
+struct WeakConnection {
+    write: std::sync::Weak<Mutex<Sink>>,
+    read: std::sync::Weak<Mutex<Source>>,
+    limiter: RateLimiter,
+    pending: std::sync::Weak<PendingMap>,
+    next_tag: std::sync::Weak<AtomicU64>,
+    read_timeout: Duration,
+    request_timeout: Option<Duration>,
+    closed: std::sync::Weak<watch::Sender<bool>>,
+    last_sent: std::sync::Weak<std::sync::Mutex<Instant>>,
+    health: std::sync::Weak<watch::Sender<Health>>,
+    channel: Channel,
+    recorder: Option<Recorder>,
+    metrics: Option<Arc<dyn Metrics>>,
+}
+
+impl WeakConnection {
+    /// Returns the connection, unless it was dropped.
+    fn upgrade(&self) -> Option<Connection> {
+        Some(Connection {
+            write: self.write.upgrade()?,
+            read: self.read.upgrade()?,
+            limiter: self.limiter.clone(),
+            pending: self.pending.upgrade()?,
+            next_tag: self.next_tag.upgrade()?,
+            read_timeout: self.read_timeout,
+            request_timeout: self.request_timeout,
+            closed: self.closed.upgrade()?,
+            last_sent: self.last_sent.upgrade()?,
+            health: self.health.upgrade()?,
+            channel: self.channel,
+            recorder: self.recorder.clone(),
+            metrics: self.metrics.clone(),
+        })
+    }
+}
+
+/// Stops a connection considered dead, see `Connection::tripwire`.
+#[derive(Debug)]
+pub struct Tripwire {
+    closed: std::sync::Weak<watch::Sender<bool>>,
+    health: std::sync::Weak<watch::Sender<Health>>,
+}
+
+impl Tripwire {
+    pub fn is_closed(&self) -> bool {
+        self.closed.upgrade().is_none_or(|closed| *closed.borrow())
+    }
+
+    /// Marks the connection as degraded and stops its background tasks. Returns false, if it was already closed.
+    pub fn trip(&self) -> bool {
+        let Some(closed) = self.closed.upgrade() else {
+            return false;
+        };
+        let closed_rx = closed.subscribe();
+        if *closed_rx.borrow() {
+            return false;
+        }
+        degrade(&self.health, &closed_rx);
+        !closed.send_replace(true)
+    }
+}
+
+/// Marks the connection as degraded, unless it was closed on purpose.
+fn degrade(health: &std::sync::Weak<watch::Sender<Health>>, closed: &watch::Receiver<bool>) {
+    if let (Some(health), false) = (health.upgrade(), *closed.borrow()) {
+        health.send_replace(Health::Degraded);
+    }
+}
+
+/// Returns the host and the port of the WebSocket url.
+pub(crate) fn target(url: &str) -> Result<(String, u16), Error> {
+    let invalid = || Error::WebSocketError(UrlError::UnsupportedUrlScheme.into());
+    let uri: Uri = url.parse().map_err(|_| invalid())?;
+    let host = uri.host().ok_or_else(invalid)?.to_owned();
+    let port = match (uri.port_u16(), uri.scheme_str()) {
+        (Some(port), _) => port,
+        (None, Some("ws")) => 80,
+        (None, Some("wss")) => 443,
+        _ => return Err(invalid()),
+    };
+    Ok((host, port))
+}
+
+pub(crate) fn io_error(err: std::io::Error) -> Error {
+    Error::WebSocketError(err.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::Value;
+    use tokio::net::TcpListener;
+
+    #[derive(Deserialize)]
+    struct Echo {
+        command: String,
+    }
+
+    #[tokio::test]
+    async fn test_transactions_are_routed_by_custom_tag() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(tcp).await.unwrap();
+
+            let mut requests = Vec::new();
+            while requests.len() < 2 {
+                if let Some(Ok(Message::Text(text))) = ws.next().await {
+                    requests.push(serde_json::from_str::<Value>(&text).unwrap());
+                }
+            }
+
+            // respond in reverse order
+            for request in requests.iter().rev() {
+                let response = serde_json::json!({
+                    "command": request["command"],
+                    "customTag": request["customTag"],
+                });
+                ws.send(Message::Text(response.to_string())).await.unwrap();
+            }
+            while ws.next().await.is_some() {}
+        });
+
+        let conn = Connection::connect(&url, &ConnectionConfig::default(), Channel::Socket)
+            .await
+            .unwrap();
+        conn.spawn_dispatching_task();
+        conn.skip_delay().await;
+
+        let (first, second) = tokio::join!(
+            conn.transaction::<_, Echo>(Command::new("first")),
+            conn.transaction::<_, Echo>(Command::new("second"))
+        );
+        assert_eq!(first.unwrap().command, "first");
+        assert_eq!(second.unwrap().command, "second");
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_keeps_connection() {
+        assert_eq!(ConnectionConfig::default().request_timeout, None);
+
+        let server = crate::mock::MockServer::start().await.unwrap();
+        server.once("getVersion", crate::mock::Reply::Silence);
+        server.on(
+            "getVersion",
+            crate::mock::Reply::Data(serde_json::json!({"version": "2.5.0"})),
+        );
+
+        let config = ConnectionConfig {
+            request_timeout: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let conn = Connection::connect(&server.url(), &config, Channel::Socket)
+            .await
+            .unwrap();
+        conn.spawn_dispatching_task();
+
+        let err = conn
+            .transaction::<_, Value>(Command::new("getVersion"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::RequestTimeout));
+        assert!(!conn.is_closed());
+        assert!(conn.transaction::<_, Value>(Command::new("getVersion")).await.is_ok());
+    }
+
+    async fn advance(duration: Duration) {
+        for _ in 0..duration.as_millis() / 10 {
+            tokio::time::advance(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_pings() {
+        use crate::cassette::Cassette;
+        use crate::limiter::RateLimiterConfig;
+
+        let config = ConnectionConfig {
+            rate_limit: RateLimiterConfig { interval: Duration::from_millis(10), ..Default::default() },
+            replay: Some(Cassette::default()),
+            ..Default::default()
+        };
+        let conn = Connection::connect("ws://localhost", &config, Channel::Socket)
+            .await
+            .unwrap();
+        let ping = r#"{"command":"ping","customTag":"keepalive"}"#;
+        conn.spawn_keepalive_task(Duration::from_millis(100), String::from(ping));
+
+        for _ in 0..10 {
+            conn.request(r#"{"command":"getVersion"}"#).await.unwrap();
+            advance(Duration::from_millis(50)).await;
+        }
+        assert_eq!(conn.limiter().stats().await.requests, 10);
+
+        advance(Duration::from_millis(300)).await;
+        assert_eq!(conn.limiter().stats().await.requests, 13);
+
+        conn.close().await;
+        advance(Duration::from_millis(300)).await;
+        assert_eq!(conn.limiter().stats().await.requests, 13);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn test_command_spans() {
+        use crate::mock::{MockServer, Reply};
+        use std::collections::HashMap;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+        type Spans = Arc<std::sync::Mutex<HashMap<Id, HashMap<String, String>>>>;
+
+        struct Fields<'a>(&'a mut HashMap<String, String>);
+
+        impl Visit for Fields<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0.insert(field.name().to_owned(), format!("{:?}", value));
+            }
+
+            fn record_str(&mut self, field: &Field, value: &str) {
+                self.0.insert(field.name().to_owned(), value.to_owned());
+            }
+        }
+
+        struct Capture(Spans);
+
+        impl<S: tracing::Subscriber> Layer<S> for Capture {
+            fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _: Context<'_, S>) {
+                let mut spans = self.0.lock().unwrap();
+                attrs.record(&mut Fields(spans.entry(id.clone()).or_default()));
+            }
+
+            fn on_record(&self, id: &Id, values: &Record<'_>, _: Context<'_, S>) {
+                let mut spans = self.0.lock().unwrap();
+                values.record(&mut Fields(spans.entry(id.clone()).or_default()));
+            }
+        }
+
+        let spans = Spans::default();
+        let _guard = tracing::subscriber::set_default(tracing_subscriber::registry().with(Capture(spans.clone())));
+
+        let server = MockServer::start().await.unwrap();
+        server.once("getSymbol", Reply::error("BE115", "Invalid symbol"));
+        let x = crate::connect(&server.credentials()).await.unwrap();
+        assert!(x.socket.get_symbol("UNKNOWN").await.is_err());
+        assert!(x.socket.get_symbol("EURUSD").await.is_ok());
+
+        let spans: Vec<_> = spans.lock().unwrap().values().cloned().collect();
+        let span = |command: &str, symbol: &str| {
+            spans
+                .iter()
+                .find(|span| span["command"] == command && span.get("symbol").is_some_and(|s| s == symbol))
+                .cloned()
+                .unwrap()
+        };
+        assert_eq!(span("getSymbol", "UNKNOWN")["outcome"], "ErrorResponse");
+        assert_eq!(span("getSymbol", "EURUSD")["outcome"], "ok");
+        assert!(spans
+            .iter()
+            .any(|span| span["command"] == "login" && span["outcome"] == "ok" && !span.contains_key("symbol")));
+    }
 }
@@ -1,7 +1,7 @@
 use crate::error::Error;
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct Credentials {
     pub account_id: String,
@@ -11,6 +11,18 @@ pub struct Credentials {
     pub safe: bool,
 }
 
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("account_id", &self.account_id)
+            .field("password", &"***")
+            .field("host", &self.host)
+            .field("type_", &self.type_)
+            .field("safe", &self.safe)
+            .finish()
+    }
+}
+
 impl Default for Credentials {
     fn default() -> Self {
         Self {
@@ -44,6 +56,14 @@ mod tests {
         assert_eq!(creds.safe, false);
     }
 
+    #[test]
+    fn test_credentials_debug_hides_password() {
+        let creds = Credentials { password: String::from("secret"), ..Default::default() };
+        let debug = format!("{:?}", creds);
+        assert!(!debug.contains("secret"));
+        assert!(debug.contains("password: \"***\""));
+    }
+
     #[test]
     fn test_credentials_loads() {
         const DATA: &str = r#"{
@@ -1,4 +1,5 @@
 use crate::enums::*;
+use crate::error::XApiErrorCode;
 
 use serde::{Deserialize, Serialize};
 
@@ -36,7 +37,14 @@ pub struct ErrorResponse {
     pub error_descr: String,
 }
 
+impl ErrorResponse {
+    pub fn code(&self) -> XApiErrorCode {
+        XApiErrorCode::from(self.error_code.as_str())
+    }
+}
+
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
 pub enum Record {
     Balance(Balance),
     Candle(Candle),
@@ -48,6 +56,22 @@ pub enum Record {
     TradeStatus(TradeStatus),
 }
 
+impl Record {
+    /// Returns the command name the record is delivered with, e.g. `tickPrices`.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Record::Balance(_) => "balance",
+            Record::Candle(_) => "candle",
+            Record::KeepAlive(_) => "keepAlive",
+            Record::News(_) => "news",
+            Record::Profit(_) => "profit",
+            Record::Tick(_) => "tickPrices",
+            Record::Trade(_) => "trade",
+            Record::TradeStatus(_) => "tradeStatus",
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Balance {
@@ -188,11 +212,11 @@ pub struct Symbol {
     pub margin_hedged: i64,
     pub margin_hedged_strong: bool,
     pub margin_maintenance: Option<i64>,
-    pub margin_mode: i64,
+    pub margin_mode: MarginMode,
     pub percentage: f64,
     pub pips_precision: Option<i64>,
     pub precision: i64,
-    pub profit_mode: i64,
+    pub profit_mode: ProfitMode,
     pub quote_id: i64,
     pub short_selling: bool,
     pub spread_raw: f64,
@@ -201,11 +225,11 @@ pub struct Symbol {
     pub step_rule_id: i64,
     pub stops_level: i64,
     #[serde(rename = "swap_rollover3days")]
-    pub swap_rollover3days: i64,
+    pub swap_rollover3days: RolloverDay,
     pub swap_enable: bool,
     pub swap_long: f64,
     pub swap_short: f64,
-    pub swap_type: i64,
+    pub swap_type: SwapType,
     pub symbol: String,
     pub tick_size: f64,
     pub tick_value: f64,
@@ -221,7 +245,7 @@ pub struct Calendar {
     pub country: String,
     pub current: String,
     pub forecast: String,
-    pub impact: String,
+    pub impact: Impact,
     pub period: String,
     pub previous: String,
     pub time: i64,
@@ -235,6 +259,36 @@ pub struct ChartRateInfo {
     pub rate_infos: Vec<RateInfo>,
 }
 
+impl ChartRateInfo {
+    /// Returns the candles with absolute prices. xAPI sends `open` multiplied by `10^digits`,
+    /// and `high`, `low` and `close` as offsets from `open`, in the same scale.
+    pub fn bars(&self) -> Vec<Bar> {
+        let scale = 10f64.powi(self.digits as i32);
+        self.rate_infos
+            .iter()
+            .map(|rate| Bar {
+                ctm: rate.ctm,
+                open: rate.open / scale,
+                high: (rate.open + rate.high) / scale,
+                low: (rate.open + rate.low) / scale,
+                close: (rate.open + rate.close) / scale,
+                vol: rate.vol,
+            })
+            .collect()
+    }
+}
+
+/// Candle with absolute prices, see `ChartRateInfo::bars`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Bar {
+    pub ctm: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub vol: f64,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct RateInfo {
@@ -386,3 +440,76 @@ pub struct Transaction {
 pub struct Order {
     pub order: i64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cassette::Cassette;
+    use crate::config::ConnectionConfig;
+
+    /// Synthetic session recorded against `MockServer`, not a live capture: `login` and two `getChartLastRequest`
+    /// commands, EURUSD M1 and PKN.PL_9 W1, with the `rateInfos` shaped as xAPI documents them.
+    const CHART_CASSETTE: &str = "tests/fixtures/chart/synthetic-get-chart-last-request.jsonl";
+
+    fn bar(ctm: i64, open: f64, high: f64, low: f64, close: f64, vol: f64) -> Bar {
+        Bar { ctm, open, high, low, close, vol }
+    }
+
+    /// Compares the prices with a tolerance, as they are computed from the digits and the open price.
+    fn assert_bars(actual: &[Bar], expected: &[Bar]) {
+        assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert_eq!((actual.ctm, actual.vol), (expected.ctm, expected.vol));
+            let prices = |bar: &Bar| [bar.open, bar.high, bar.low, bar.close];
+            for (actual_price, expected_price) in prices(actual).into_iter().zip(prices(expected)) {
+                assert!(
+                    (actual_price - expected_price).abs() < 1e-9,
+                    "{:?} != {:?}",
+                    actual,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_bars() {
+        assert!(ChartRateInfo::default().bars().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_chart_request_bars() {
+        let config = ConnectionConfig {
+            replay: Some(Cassette::load(CHART_CASSETTE).unwrap()),
+            ..Default::default()
+        };
+        let x = crate::connect_with_config(&Default::default(), &config).await.unwrap();
+
+        let response = x
+            .socket
+            .get_chart_last_request("EURUSD", 1701162000000, Period::M1)
+            .await
+            .unwrap();
+        assert_bars(
+            &response.return_data.bars(),
+            &[
+                bar(1701162000000, 1.09512, 1.09516, 1.09505, 1.09509, 152.0),
+                bar(1701162060000, 1.09509, 1.09521, 1.09509, 1.09519, 98.0),
+                bar(1701162120000, 1.09519, 1.09519, 1.09518, 1.09519, 11.0),
+            ],
+        );
+
+        let response = x
+            .socket
+            .get_chart_last_request("PKN.PL_9", 1701126000000, Period::W1)
+            .await
+            .unwrap();
+        assert_bars(
+            &response.return_data.bars(),
+            &[
+                bar(1701039600000, 65.83, 67.07, 64.45, 64.88, 5210483.0),
+                bar(1701644400000, 64.87, 67.33, 64.56, 66.98, 6012278.0),
+            ],
+        );
+    }
+}
@@ -0,0 +1,238 @@
+//! `chrono` conversions of the millisecond timestamps, enabled with the `chrono` feature.
+
+use crate::data::*;
+use crate::enums::Period;
+use crate::error::Error;
+use crate::socket::Socket;
+
+use chrono::{DateTime, NaiveTime, TimeZone, Timelike, Utc};
+
+/// Milliseconds since the Unix epoch, as sent by xAPI.
+trait Millis {
+    fn to_datetime(self) -> Option<DateTime<Utc>>;
+}
+
+impl Millis for i64 {
+    fn to_datetime(self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp_millis(self)
+    }
+}
+
+impl Millis for Option<i64> {
+    fn to_datetime(self) -> Option<DateTime<Utc>> {
+        self.and_then(DateTime::from_timestamp_millis)
+    }
+}
+
+/// Defines accessors returning the timestamp fields as date-times.
+/// They return `None` for a missing field, or for a value out of the `chrono` range.
+macro_rules! datetime_accessors {
+    ($($type:ty { $($method:ident => $field:ident,)* })*) => {
+        $(
+            impl $type {
+                $(
+                    #[doc = concat!("Returns `", stringify!($field), "` as a date-time.")]
+                    pub fn $method(&self) -> Option<DateTime<Utc>> {
+                        self.$field.to_datetime()
+                    }
+                )*
+            }
+        )*
+    };
+}
+
+datetime_accessors! {
+    Bar { ctm_datetime => ctm, }
+    Calendar { datetime => time, }
+    Candle { ctm_datetime => ctm, }
+    IBData { datetime => timestamp, }
+    KeepAlive { datetime => timestamp, }
+    News { datetime => time, }
+    RateInfo { ctm_datetime => ctm, }
+    ServerTime { datetime => time, }
+    Symbol { datetime => time, expiration_datetime => expiration, starting_datetime => starting, }
+    Tick { datetime => timestamp, }
+    Trade {
+        open_datetime => open_time,
+        close_datetime => close_time,
+        expiration_datetime => expiration,
+        datetime => timestamp,
+    }
+    Transaction { expiration_datetime => expiration, }
+}
+
+/// Converts milliseconds since midnight to a time of day. Returns `None` from the end of the day, 24:00, on.
+fn time_of_day(millis: i64) -> Option<NaiveTime> {
+    let millis = u32::try_from(millis).ok()?;
+    NaiveTime::from_num_seconds_from_midnight_opt(millis / 1000, millis % 1000 * 1_000_000)
+}
+
+/// Converts a time of day to milliseconds since midnight.
+fn millis_of_day(time: NaiveTime) -> i64 {
+    i64::from(time.num_seconds_from_midnight()) * 1000 + i64::from(time.nanosecond() / 1_000_000)
+}
+
+/// Converts `1` (Monday) to `7` (Sunday), as sent by xAPI.
+fn weekday(day: i64) -> Option<chrono::Weekday> {
+    u8::try_from(day - 1)
+        .ok()
+        .and_then(|day| chrono::Weekday::try_from(day).ok())
+}
+
+macro_rules! hours_accessors {
+    ($($type:ty),*) => {
+        $(
+            impl $type {
+                pub fn weekday(&self) -> Option<chrono::Weekday> {
+                    weekday(self.day)
+                }
+
+                /// Returns `from_t` as a time of day.
+                pub fn from_time(&self) -> Option<NaiveTime> {
+                    time_of_day(self.from_t)
+                }
+
+                /// Returns `to_t` as a time of day. Returns `None` for the end of the day, 24:00,
+                /// which is not a `NaiveTime`.
+                pub fn to_time(&self) -> Option<NaiveTime> {
+                    time_of_day(self.to_t)
+                }
+
+                /// Whether the time of day is within the hours. The interval is half-open:
+                /// `from_t` is included and `to_t` is not, so `to_t` of 24:00 includes the last millisecond of the day.
+                pub fn contains(&self, time: NaiveTime) -> bool {
+                    (self.from_t..self.to_t).contains(&millis_of_day(time))
+                }
+            }
+        )*
+    };
+}
+
+hours_accessors!(Quote, Trading);
+
+impl Socket {
+    /// Same as `get_chart_last_request`, with a date-time start.
+    pub async fn get_chart_last_request_since<Tz: TimeZone>(
+        &self,
+        symbol: &str,
+        start: DateTime<Tz>,
+        period: Period,
+    ) -> Result<Response<ChartRateInfo>, Error> {
+        self.get_chart_last_request(symbol, start.timestamp_millis(), period)
+            .await
+    }
+
+    /// Same as `get_chart_range_request`, with date-time start and end.
+    pub async fn get_chart_range_request_between<Tz: TimeZone>(
+        &self,
+        symbol: &str,
+        start: DateTime<Tz>,
+        end: DateTime<Tz>,
+        period: Period,
+        ticks: i64,
+    ) -> Result<Response<ChartRateInfo>, Error> {
+        self.get_chart_range_request(symbol, start.timestamp_millis(), end.timestamp_millis(), period, ticks)
+            .await
+    }
+
+    /// Same as `get_ibs_history`, with date-time start and end.
+    pub async fn get_ibs_history_between<Tz: TimeZone>(
+        &self,
+        start: DateTime<Tz>,
+        end: DateTime<Tz>,
+    ) -> Result<Response<Vec<IBData>>, Error> {
+        self.get_ibs_history(start.timestamp_millis(), end.timestamp_millis())
+            .await
+    }
+
+    /// Same as `get_news`, with date-time start and end.
+    pub async fn get_news_between<Tz: TimeZone>(
+        &self,
+        start: DateTime<Tz>,
+        end: DateTime<Tz>,
+    ) -> Result<Response<Vec<News>>, Error> {
+        self.get_news(start.timestamp_millis(), end.timestamp_millis()).await
+    }
+
+    /// Same as `get_trades_history`, with date-time start and end.
+    pub async fn get_trades_history_between<Tz: TimeZone>(
+        &self,
+        start: DateTime<Tz>,
+        end: DateTime<Tz>,
+    ) -> Result<Response<Vec<Trade>>, Error> {
+        self.get_trades_history(start.timestamp_millis(), end.timestamp_millis())
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Reply};
+
+    use serde_json::json;
+    use tokio::time::Duration;
+
+    #[test]
+    fn test_datetime_accessors() {
+        let trade = Trade {
+            open_time: 1_700_000_000_123,
+            close_time: None,
+            ..Default::default()
+        };
+        let open = trade.open_datetime().unwrap();
+        assert_eq!(open.to_rfc3339(), "2023-11-14T22:13:20.123+00:00");
+        assert_eq!(trade.close_datetime(), None);
+
+        let tick = Tick { timestamp: 0, ..Default::default() };
+        assert_eq!(tick.datetime(), Some(DateTime::UNIX_EPOCH));
+        let time = ServerTime { time: i64::MAX, ..Default::default() };
+        assert_eq!(time.datetime(), None);
+    }
+
+    #[test]
+    fn test_trading_hours() {
+        let trading = Trading { day: 1, from_t: 3_600_000, to_t: 86_400_000 };
+        assert_eq!(trading.weekday(), Some(chrono::Weekday::Mon));
+        assert_eq!(trading.from_time(), NaiveTime::from_hms_opt(1, 0, 0));
+        assert_eq!(trading.to_time(), None);
+
+        let quote = Quote { day: 7, from_t: 45_296_789, to_t: 0 };
+        assert_eq!(quote.weekday(), Some(chrono::Weekday::Sun));
+        assert_eq!(quote.from_time(), NaiveTime::from_hms_milli_opt(12, 34, 56, 789));
+        assert_eq!(quote.to_time(), Some(NaiveTime::MIN));
+        assert_eq!(Quote { day: 8, ..Default::default() }.weekday(), None);
+        assert_eq!(Quote { from_t: -1, ..Default::default() }.from_time(), None);
+    }
+
+    #[test]
+    fn test_trading_hours_contain_full_day() {
+        let time = |h, m, s, ms| NaiveTime::from_hms_milli_opt(h, m, s, ms).unwrap();
+        let day = Trading { day: 1, from_t: 0, to_t: 86_400_000 };
+        assert!(day.contains(NaiveTime::MIN));
+        assert!(day.contains(time(12, 0, 0, 0)));
+        assert!(day.contains(time(23, 59, 59, 999)));
+
+        let morning = Trading { day: 1, from_t: 3_600_000, to_t: 7_200_000 };
+        assert!(!morning.contains(time(0, 59, 59, 999)));
+        assert!(morning.contains(time(1, 0, 0, 0)));
+        assert!(morning.contains(time(1, 59, 59, 999)));
+        assert!(!morning.contains(time(2, 0, 0, 0)));
+    }
+
+    #[tokio::test]
+    async fn test_date_time_arguments() {
+        let server = MockServer::start().await.unwrap();
+        server.once("getNews", Reply::Data(json!([])));
+        let x = crate::connect(&server.credentials()).await.unwrap();
+        let start = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        x.socket
+            .get_news_between(start, start + chrono::TimeDelta::days(1))
+            .await
+            .unwrap();
+
+        let request = server.wait_for("getNews", Duration::from_secs(5)).await.unwrap();
+        assert_eq!(request.json["arguments"]["start"], 1_704_164_645_000i64);
+        assert_eq!(request.json["arguments"]["end"], 1_704_251_045_000i64);
+    }
+}
@@ -0,0 +1,278 @@
+//! `rust_decimal` conversions of prices and volumes, enabled with the `rust_decimal` feature.
+//!
+//! The fields stay `f64`, so that the feature is additive. Requests taking `Decimal` arguments round them
+//! to the symbol precision and lot step. A `Decimal` rounded to at most 15 significant digits converts to the `f64`,
+//! which serializes back to the same digits, so rounding before transmission is exact.
+
+use crate::data::*;
+use crate::enums::TradeCmd;
+use crate::error::Error;
+use crate::socket::Socket;
+
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// Converts an `f64`. Returns `None` for NaN, infinity, or a value out of the `Decimal` range.
+fn decimal(value: f64) -> Option<Decimal> {
+    Decimal::try_from(value).ok()
+}
+
+/// Converts a `Decimal` rounded to the symbol. Every such value is in the `f64` range.
+fn float(value: Decimal) -> f64 {
+    value.to_f64().expect("Decimal is in the f64 range")
+}
+
+/// Defines accessors returning the `f64` fields as decimals.
+macro_rules! decimal_accessors {
+    ($($type:ty { $($method:ident => $field:ident,)* })*) => {
+        $(
+            impl $type {
+                $(
+                    #[doc = concat!("Returns `", stringify!($field), "` as a decimal.")]
+                    pub fn $method(&self) -> Option<Decimal> {
+                        decimal(self.$field)
+                    }
+                )*
+            }
+        )*
+    };
+}
+
+decimal_accessors! {
+    Symbol {
+        ask_decimal => ask,
+        bid_decimal => bid,
+        high_decimal => high,
+        low_decimal => low,
+        lot_max_decimal => lot_max,
+        lot_min_decimal => lot_min,
+        lot_step_decimal => lot_step,
+        tick_size_decimal => tick_size,
+    }
+    Tick {
+        ask_decimal => ask,
+        bid_decimal => bid,
+        high_decimal => high,
+        low_decimal => low,
+    }
+    Trade {
+        open_price_decimal => open_price,
+        close_price_decimal => close_price,
+        sl_decimal => sl,
+        tp_decimal => tp,
+        volume_decimal => volume,
+    }
+    Transaction {
+        price_decimal => price,
+        sl_decimal => sl,
+        tp_decimal => tp,
+        volume_decimal => volume,
+    }
+}
+
+impl Symbol {
+    /// Rounds the price to `precision` decimal places, half away from zero. Returned as is, when `precision` is
+    /// negative or beyond the `Decimal` scale.
+    pub fn round_price(&self, price: Decimal) -> Decimal {
+        let precision = match u32::try_from(self.precision) {
+            Ok(precision) if precision <= Decimal::MAX_SCALE => precision,
+            _ => return price,
+        };
+        price
+            .round_dp_with_strategy(precision, RoundingStrategy::MidpointAwayFromZero)
+            .normalize()
+    }
+
+    /// Rounds the volume to a multiple of `lot_step`, half away from zero. Returned as is, without a `lot_step`.
+    pub fn round_volume(&self, volume: Decimal) -> Decimal {
+        let step = match self.lot_step_decimal() {
+            Some(step) if step > Decimal::ZERO => step,
+            _ => return volume,
+        };
+        ((volume / step).round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero) * step).normalize()
+    }
+}
+
+impl Transaction {
+    /// Sets the price, stop loss and take profit, rounded to the symbol precision.
+    pub fn with_prices(self, symbol: &Symbol, price: Decimal, sl: Decimal, tp: Decimal) -> Transaction {
+        Transaction {
+            price: float(symbol.round_price(price)),
+            sl: float(symbol.round_price(sl)),
+            tp: float(symbol.round_price(tp)),
+            ..self
+        }
+    }
+
+    /// Sets the volume, rounded to the symbol lot step.
+    pub fn with_volume(self, symbol: &Symbol, volume: Decimal) -> Transaction {
+        Transaction { volume: float(symbol.round_volume(volume)), ..self }
+    }
+
+    /// Rounds the price, stop loss and take profit to the symbol precision, and the volume to the symbol lot step.
+    /// Returns `None`, if any of them is not a finite number in the `Decimal` range.
+    pub fn round_to(self, symbol: &Symbol) -> Option<Transaction> {
+        let price = self.price_decimal()?;
+        let sl = self.sl_decimal()?;
+        let tp = self.tp_decimal()?;
+        let volume = self.volume_decimal()?;
+        Some(self.with_prices(symbol, price, sl, tp).with_volume(symbol, volume))
+    }
+}
+
+impl Socket {
+    /// Same as `trade_transaction`, with the prices and the volume rounded to the symbol, see `Transaction::round_to`.
+    /// Fails with `Error::InvalidNumber`, if any of them cannot be rounded.
+    pub async fn trade_transaction_rounded(
+        &self,
+        transaction: Transaction,
+        symbol: &Symbol,
+    ) -> Result<Response<Order>, Error> {
+        let values = [transaction.price, transaction.sl, transaction.tp, transaction.volume];
+        let Some(transaction) = transaction.round_to(symbol) else {
+            let value = values
+                .into_iter()
+                .find(|value| decimal(*value).is_none())
+                .unwrap_or_default();
+            return Err(Error::InvalidNumber { value });
+        };
+        self.trade_transaction(transaction).await
+    }
+
+    /// Same as `get_commission_def`, with the volume rounded to the symbol lot step.
+    pub async fn get_commission_def_decimal(
+        &self,
+        symbol: &Symbol,
+        volume: Decimal,
+    ) -> Result<Response<CommissionDef>, Error> {
+        self.get_commission_def(&symbol.symbol, float(symbol.round_volume(volume)))
+            .await
+    }
+
+    /// Same as `get_margin_trade`, with the volume rounded to the symbol lot step.
+    pub async fn get_margin_trade_decimal(
+        &self,
+        symbol: &Symbol,
+        volume: Decimal,
+    ) -> Result<Response<MarginTrade>, Error> {
+        self.get_margin_trade(&symbol.symbol, float(symbol.round_volume(volume)))
+            .await
+    }
+
+    /// Same as `get_profit_calculation`, with the prices rounded to the symbol precision
+    /// and the volume to the symbol lot step.
+    pub async fn get_profit_calculation_decimal(
+        &self,
+        symbol: &Symbol,
+        cmd: TradeCmd,
+        open_price: Decimal,
+        close_price: Decimal,
+        volume: Decimal,
+    ) -> Result<Response<ProfitCalculation>, Error> {
+        let open_price = float(symbol.round_price(open_price));
+        let close_price = float(symbol.round_price(close_price));
+        let volume = float(symbol.round_volume(volume));
+        self.get_profit_calculation(&symbol.symbol, cmd, open_price, close_price, volume)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockServer, Reply};
+
+    use serde_json::json;
+    use std::str::FromStr;
+    use tokio::time::Duration;
+
+    fn eurusd() -> Symbol {
+        Symbol {
+            symbol: String::from("EURUSD"),
+            precision: 5,
+            lot_step: 0.01,
+            ..Default::default()
+        }
+    }
+
+    fn dec(value: &str) -> Decimal {
+        Decimal::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn test_rounding() {
+        let symbol = eurusd();
+        assert_eq!(symbol.round_price(dec("1.0850000001")), dec("1.085"));
+        assert_eq!(symbol.round_price(dec("1.085015")), dec("1.08502"));
+        let invalid = Symbol { precision: -1, ..eurusd() };
+        assert_eq!(invalid.round_price(dec("1.085015")), dec("1.085015"));
+        let invalid = Symbol { precision: 100, ..eurusd() };
+        assert_eq!(invalid.round_price(dec("1.085015")), dec("1.085015"));
+        assert_eq!(symbol.round_volume(dec("0.123")), dec("0.12"));
+        assert_eq!(symbol.round_volume(dec("0.125")), dec("0.13"));
+        assert_eq!(Symbol::default().round_volume(dec("0.123")), dec("0.123"));
+
+        let tick = Tick { ask: 1.1, bid: f64::NAN, ..Default::default() };
+        assert_eq!(tick.ask_decimal(), Some(dec("1.1")));
+        assert_eq!(tick.bid_decimal(), None);
+    }
+
+    #[tokio::test]
+    async fn test_rounded_transaction() {
+        let server = MockServer::start().await.unwrap();
+        let x = crate::connect(&server.credentials()).await.unwrap();
+
+        let transaction = Transaction {
+            symbol: String::from("EURUSD"),
+            price: 1.0 + 0.085 + 0.0000001,
+            volume: 0.1 + 0.2 + 0.001,
+            ..Default::default()
+        };
+        x.socket
+            .trade_transaction_rounded(transaction.clone(), &eurusd())
+            .await
+            .unwrap();
+        let request = server
+            .wait_for("tradeTransaction", Duration::from_secs(5))
+            .await
+            .unwrap();
+        let info = &request.json["arguments"]["tradeTransInfo"];
+        assert_eq!(info["price"].to_string(), "1.085");
+        assert_eq!(info["volume"].to_string(), "0.3");
+
+        let invalid = Transaction { sl: f64::INFINITY, ..transaction };
+        let err = x
+            .socket
+            .trade_transaction_rounded(invalid, &eurusd())
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidNumber { value } if value == f64::INFINITY));
+    }
+
+    #[tokio::test]
+    async fn test_decimal_arguments() {
+        let server = MockServer::start().await.unwrap();
+        server.once("getMarginTrade", Reply::Data(json!({ "margin": 1.0 })));
+        server.once("getProfitCalculation", Reply::Data(json!({ "profit": 1.0 })));
+        let x = crate::connect(&server.credentials()).await.unwrap();
+
+        x.socket
+            .get_margin_trade_decimal(&eurusd(), dec("0.123"))
+            .await
+            .unwrap();
+        let request = server.wait_for("getMarginTrade", Duration::from_secs(5)).await.unwrap();
+        assert_eq!(request.json["arguments"]["volume"].to_string(), "0.12");
+
+        x.socket
+            .get_profit_calculation_decimal(&eurusd(), TradeCmd::Buy, dec("1.0850004"), dec("1.09"), dec("1"))
+            .await
+            .unwrap();
+        let request = server
+            .wait_for("getProfitCalculation", Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(request.json["arguments"]["openPrice"].to_string(), "1.085");
+        assert_eq!(request.json["arguments"]["closePrice"].to_string(), "1.09");
+        assert_eq!(request.json["arguments"]["volume"].to_string(), "1.0");
+    }
+}
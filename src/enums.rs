@@ -1,139 +1,375 @@
+use crate::error::Error;
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
-#[serde(from = "i64")]
-pub enum TradeCmd {
-    /// Buy
-    Buy = 0,
-    /// Sell
-    Sell = 1,
-    /// Buy limit
-    BuyLimit = 2,
-    /// Sell limit
-    SellLimit = 3,
-    /// Suy stop
-    BuyStop = 4,
-    /// Sell stop
-    SellStop = 5,
-    /// Read only. Used in getTradesHistory for manager's deposit/withdrawal operations (profit>0 for deposit, profit<0 for withdrawal).
-    Balance = 6,
-    /// Read only
-    Credit = 7,
-    #[default]
-    Invalid = -1,
-}
-
-impl From<i64> for TradeCmd {
-    fn from(value: i64) -> Self {
-        match value {
-            0 => TradeCmd::Buy,
-            1 => TradeCmd::Sell,
-            2 => TradeCmd::BuyLimit,
-            3 => TradeCmd::SellLimit,
-            4 => TradeCmd::BuyStop,
-            5 => TradeCmd::SellStop,
-            6 => TradeCmd::Balance,
-            7 => TradeCmd::Credit,
-            _ => TradeCmd::Invalid,
+/// Defines an enum of integer codes, which keeps values not known to this library in `Unknown`,
+/// so they survive a round trip through serde. `TryFrom<i64>` accepts only the known values, `from_raw` any value.
+/// Enums declared `with Invalid` get an `Invalid` variant for -1, which xAPI sends for a missing value,
+/// and default to it. Others default to the value 0.
+macro_rules! int_enum {
+    (@default $name:ident $invalid:ident) => {
+        $name::$invalid
+    };
+    (@default $name:ident) => {
+        $name::from_raw(0)
+    };
+    ($(#[$meta:meta])* $name:ident $(with $invalid:ident)? { $($(#[$doc:meta])* $variant:ident = $value:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, Hash)]
+        #[serde(into = "i64")]
+        pub enum $name {
+            $($(#[$doc])* $variant,)*
+            $(
+                /// Missing value, encoded as -1
+                $invalid,
+            )?
+            /// Value not known to this library
+            Unknown(i64),
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                int_enum!(@default $name $($invalid)?)
+            }
+        }
+
+        impl $name {
+            pub fn from_raw(value: i64) -> Self {
+                match value {
+                    $($value => $name::$variant,)*
+                    $(-1 => $name::$invalid,)?
+                    _ => $name::Unknown(value),
+                }
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                i64::deserialize(deserializer).map($name::from_raw)
+            }
+        }
+
+        impl From<$name> for i64 {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => $value,)*
+                    $($name::$invalid => -1,)?
+                    $name::Unknown(value) => value,
+                }
+            }
+        }
+
+        impl TryFrom<i64> for $name {
+            type Error = Error;
+
+            fn try_from(value: i64) -> Result<Self, Error> {
+                match $name::from_raw(value) {
+                    $($name::$invalid => Err(Error::UnknownValue { value }),)?
+                    $name::Unknown(_) => Err(Error::UnknownValue { value }),
+                    known => Ok(known),
+                }
+            }
         }
+    };
+}
+
+int_enum! {
+    TradeCmd with Invalid {
+        /// Buy
+        Buy = 0,
+        /// Sell
+        Sell = 1,
+        /// Buy limit
+        BuyLimit = 2,
+        /// Sell limit
+        SellLimit = 3,
+        /// Buy stop
+        BuyStop = 4,
+        /// Sell stop
+        SellStop = 5,
+        /// Read only. Used in getTradesHistory for manager's deposit/withdrawal operations (profit>0 for deposit, profit<0 for withdrawal).
+        Balance = 6,
+        /// Read only
+        Credit = 7,
+    }
+}
+
+int_enum! {
+    TradeType with Invalid {
+        /// Order open, used for opening orders
+        Open = 0,
+        /// Order pending, only used in the streaming getTrades command
+        Pending = 1,
+        /// Order close
+        Close = 2,
+        /// Order modify, only used in the tradeTransaction command
+        Modify = 3,
+        /// Order delete, only used in the tradeTransaction command
+        Delete = 4,
+    }
+}
+
+int_enum! {
+    RequestStatus with Invalid {
+        /// Error occurred while executing the transaction
+        Error = 0,
+        /// The transaction is pending
+        Pending = 1,
+        /// The transaction has been executed successfully
+        Accepted = 3,
+        /// The transaction has been rejected
+        Rejected = 4,
+    }
+}
+
+int_enum! {
+    Period with Invalid {
+        /// 1 minute
+        M1 = 1,
+        /// 5 minutes
+        M5 = 5,
+        /// 15 minutes
+        M15 = 15,
+        /// 30 minutes
+        M30 = 30,
+        /// 60 minutes (1 hour)
+        H1 = 60,
+        /// 240 minutes (4 hours)
+        H4 = 240,
+        /// 1440 minutes (1 day)
+        D1 = 1440,
+        /// 10080 minutes (1 week)
+        W1 = 10080,
+        /// 43200 minutes (30 days)
+        MN1 = 43200,
+    }
+}
+
+int_enum! {
+    /// Margin calculation mode of a symbol
+    MarginMode {
+        /// Forex
+        Forex = 101,
+        /// CFD leveraged
+        CfdLeveraged = 102,
+        /// CFD
+        Cfd = 103,
+        /// Futures
+        Futures = 104,
+    }
+}
+
+int_enum! {
+    /// Profit calculation mode of a symbol
+    ProfitMode {
+        /// Forex
+        Forex = 5,
+        /// CFD
+        Cfd = 6,
+    }
+}
+
+int_enum! {
+    /// Unit of the swap rates of a symbol
+    SwapType {
+        /// Swap in points
+        Points = 0,
+        /// Swap in money
+        Dollars = 1,
+        /// Swap in percent, as interest
+        Interest = 2,
+        /// Swap in the margin currency
+        MarginCurrency = 3,
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
-#[serde(from = "i64")]
-pub enum TradeType {
-    /// Order open, used for opening orders
-    Open = 0,
-    /// Order pending, only used in the streaming getTrades command
-    Pending = 1,
-    /// Order close
-    Close = 2,
-    /// Order modify, only used in the tradeTransaction command
-    Modify = 3,
-    /// Order delete, only used in the tradeTransaction command
-    Delete = 4,
-    #[default]
-    Invalid = -1,
-}
-
-impl From<i64> for TradeType {
-    fn from(value: i64) -> Self {
-        match value {
-            0 => TradeType::Open,
-            1 => TradeType::Pending,
-            2 => TradeType::Close,
-            3 => TradeType::Modify,
-            4 => TradeType::Delete,
-            _ => TradeType::Invalid,
+int_enum! {
+    /// Day of the week of the triple swap rollover
+    RolloverDay {
+        Sunday = 0,
+        Monday = 1,
+        Tuesday = 2,
+        Wednesday = 3,
+        Thursday = 4,
+        Friday = 5,
+        Saturday = 6,
+    }
+}
+
+/// Expected market impact of a calendar event, sent as a string code
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Impact {
+    /// "1"
+    Low,
+    /// "2"
+    Medium,
+    /// "3"
+    High,
+    /// Code not known to this library
+    Unknown(String),
+}
+
+impl Default for Impact {
+    fn default() -> Self {
+        Impact::Unknown(String::new())
+    }
+}
+
+impl Impact {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Impact::Low => "1",
+            Impact::Medium => "2",
+            Impact::High => "3",
+            Impact::Unknown(code) => code,
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
-#[serde(from = "i64")]
-pub enum RequestStatus {
-    /// Error occurred while executing the transaction
-    Error = 0,
-    /// The transaction is pending
-    Pending = 1,
-    /// The transaction has been executed successfully
-    Accepted = 3,
-    /// The transaction has been rejected
-    Rejected = 4,
-    #[default]
-    Invalid = -1,
-}
-
-impl From<i64> for RequestStatus {
-    fn from(value: i64) -> Self {
-        match value {
-            0 => RequestStatus::Error,
-            1 => RequestStatus::Pending,
-            3 => RequestStatus::Accepted,
-            4 => RequestStatus::Rejected,
-            _ => RequestStatus::Invalid,
+impl From<&str> for Impact {
+    fn from(code: &str) -> Self {
+        match code {
+            "1" => Impact::Low,
+            "2" => Impact::Medium,
+            "3" => Impact::High,
+            _ => Impact::Unknown(String::from(code)),
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
-#[serde(from = "i64")]
-pub enum Period {
-    /// 1 minute
-    M1 = 1,
-    /// 5 minutes
-    M5 = 5,
-    /// 15 minutes
-    M15 = 15,
-    /// 30 minutes
-    M30 = 30,
-    /// 60 minutes (1 hour)
-    H1 = 60,
-    /// 240 minutes (4 hours)
-    H4 = 240,
-    /// 1440 minutes (1 day)
-    D1 = 1440,
-    /// 10080 minutes (1 week)
-    W1 = 10080,
-    /// 43200 minutes (30 days)
-    MN1 = 43200,
-    #[default]
-    Invalid = -1,
-}
-
-impl From<i64> for Period {
-    fn from(value: i64) -> Self {
-        match value {
-            1 => Period::M1,
-            5 => Period::M5,
-            15 => Period::M15,
-            30 => Period::M30,
-            60 => Period::H1,
-            240 => Period::H4,
-            1440 => Period::D1,
-            10080 => Period::W1,
-            43200 => Period::MN1,
-            _ => Period::Invalid,
+impl Serialize for Impact {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Impact {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(|code| Impact::from(code.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip<T>(value: i64, expected: T)
+    where
+        T: Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug + Into<i64> + Copy,
+    {
+        let parsed: T = serde_json::from_str(&value.to_string()).unwrap();
+        assert_eq!(parsed, expected);
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), value.to_string());
+        assert_eq!(parsed.into(), value);
+    }
+
+    #[test]
+    fn test_trade_cmd() {
+        let known = [
+            (0, TradeCmd::Buy),
+            (1, TradeCmd::Sell),
+            (2, TradeCmd::BuyLimit),
+            (3, TradeCmd::SellLimit),
+            (4, TradeCmd::BuyStop),
+            (5, TradeCmd::SellStop),
+            (6, TradeCmd::Balance),
+            (7, TradeCmd::Credit),
+        ];
+        for (value, cmd) in known {
+            assert_round_trip(value, cmd);
+            assert_eq!(TradeCmd::try_from(value).unwrap(), cmd);
+        }
+        assert_round_trip(8, TradeCmd::Unknown(8));
+        assert_round_trip(-1, TradeCmd::Invalid);
+        assert!(matches!(TradeCmd::try_from(8), Err(Error::UnknownValue { value: 8 })));
+        assert!(TradeCmd::try_from(-1).is_err());
+    }
+
+    #[test]
+    fn test_trade_type() {
+        let known = [
+            (0, TradeType::Open),
+            (1, TradeType::Pending),
+            (2, TradeType::Close),
+            (3, TradeType::Modify),
+            (4, TradeType::Delete),
+        ];
+        for (value, type_) in known {
+            assert_round_trip(value, type_);
+            assert_eq!(TradeType::try_from(value).unwrap(), type_);
+        }
+        assert_round_trip(5, TradeType::Unknown(5));
+        assert!(TradeType::try_from(5).is_err());
+    }
+
+    #[test]
+    fn test_request_status() {
+        let known = [
+            (0, RequestStatus::Error),
+            (1, RequestStatus::Pending),
+            (3, RequestStatus::Accepted),
+            (4, RequestStatus::Rejected),
+        ];
+        for (value, status) in known {
+            assert_round_trip(value, status);
+            assert_eq!(RequestStatus::try_from(value).unwrap(), status);
+        }
+        assert_round_trip(2, RequestStatus::Unknown(2));
+        assert!(RequestStatus::try_from(2).is_err());
+    }
+
+    #[test]
+    fn test_period() {
+        let known = [
+            (1, Period::M1),
+            (5, Period::M5),
+            (15, Period::M15),
+            (30, Period::M30),
+            (60, Period::H1),
+            (240, Period::H4),
+            (1440, Period::D1),
+            (10080, Period::W1),
+            (43200, Period::MN1),
+        ];
+        for (value, period) in known {
+            assert_round_trip(value, period);
+            assert_eq!(Period::try_from(value).unwrap(), period);
+        }
+        assert_round_trip(120, Period::Unknown(120));
+        assert!(Period::try_from(120).is_err());
+    }
+
+    #[test]
+    fn test_symbol_codes() {
+        assert_round_trip(101, MarginMode::Forex);
+        assert_round_trip(102, MarginMode::CfdLeveraged);
+        assert_round_trip(103, MarginMode::Cfd);
+        assert_round_trip(104, MarginMode::Futures);
+        assert_round_trip(5, ProfitMode::Forex);
+        assert_round_trip(6, ProfitMode::Cfd);
+        assert_round_trip(0, SwapType::Points);
+        assert_round_trip(1, SwapType::Dollars);
+        assert_round_trip(2, SwapType::Interest);
+        assert_round_trip(3, SwapType::MarginCurrency);
+        assert_round_trip(3, RolloverDay::Wednesday);
+        assert_round_trip(7, RolloverDay::Unknown(7));
+        assert_round_trip(-1, RolloverDay::Unknown(-1));
+        assert_round_trip(-1, MarginMode::Unknown(-1));
+        assert!(MarginMode::try_from(-1).is_err());
+        assert_eq!(MarginMode::default(), MarginMode::Unknown(0));
+        assert_eq!(SwapType::default(), SwapType::Points);
+        assert_eq!(TradeCmd::default(), TradeCmd::Invalid);
+    }
+
+    #[test]
+    fn test_impact() {
+        for (code, impact) in [("1", Impact::Low), ("2", Impact::Medium), ("3", Impact::High)] {
+            let json = format!("\"{}\"", code);
+            assert_eq!(serde_json::from_str::<Impact>(&json).unwrap(), impact);
+            assert_eq!(serde_json::to_string(&impact).unwrap(), json);
         }
+        let unknown: Impact = serde_json::from_str("\"4\"").unwrap();
+        assert_eq!(unknown, Impact::Unknown(String::from("4")));
+        assert_eq!(serde_json::to_string(&unknown).unwrap(), "\"4\"");
     }
 }
@@ -8,12 +8,26 @@ pub enum Error {
     ConnectionClosed,
     #[error("Connection timeout")]
     ConnectionTimeout,
+    #[error("Request timeout")]
+    RequestTimeout,
     #[error("No data received")]
     NoDataReceived,
+    #[error("Stale connection, no KeepAlive received")]
+    StaleConnection,
     #[error("Trading is disabled when safe=true")]
     TradingIsDisabled,
     #[error("Error received: {response:?}")]
     ErrorResponse { response: ErrorResponse },
+    #[error("Receiver lagged behind, {skipped} records skipped")]
+    Lagged { skipped: u64 },
+    #[error("Proxy error: {reason}")]
+    ProxyError { reason: String },
+    #[error("TLS error: {reason}")]
+    TlsError { reason: String },
+    #[error("Invalid number: {value}")]
+    InvalidNumber { value: f64 },
+    #[error("Unknown value: {value}")]
+    UnknownValue { value: i64 },
     #[error("Unknown record: {record:?}")]
     UnknownRecord { record: String },
     #[error("JsonParseError: {0}")]
@@ -21,3 +35,336 @@ pub enum Error {
     #[error("Websocket error: {0}")]
     WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
 }
+
+impl Error {
+    /// Whether the error means the underlying WebSocket connection is no longer usable.
+    /// A proxy, which refused the tunnel or the credentials, is not a connection error, but its I/O failures are.
+    pub fn is_connection_error(&self) -> bool {
+        matches!(
+            self,
+            Error::ConnectionClosed
+                | Error::ConnectionTimeout
+                | Error::NoDataReceived
+                | Error::StaleConnection
+                | Error::WebSocketError(_)
+        )
+    }
+
+    /// Returns the name of the variant, e.g. `ConnectionTimeout`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::ConnectionClosed => "ConnectionClosed",
+            Error::ConnectionTimeout => "ConnectionTimeout",
+            Error::RequestTimeout => "RequestTimeout",
+            Error::NoDataReceived => "NoDataReceived",
+            Error::StaleConnection => "StaleConnection",
+            Error::TradingIsDisabled => "TradingIsDisabled",
+            Error::ErrorResponse { .. } => "ErrorResponse",
+            Error::Lagged { .. } => "Lagged",
+            Error::ProxyError { .. } => "ProxyError",
+            Error::TlsError { .. } => "TlsError",
+            Error::InvalidNumber { .. } => "InvalidNumber",
+            Error::UnknownValue { .. } => "UnknownValue",
+            Error::UnknownRecord { .. } => "UnknownRecord",
+            Error::JsonParseError(_) => "JsonParseError",
+            Error::WebSocketError(_) => "WebSocketError",
+        }
+    }
+
+    /// Returns the xAPI error code of an `ErrorResponse`.
+    pub fn error_code(&self) -> Option<XApiErrorCode> {
+        match self {
+            Error::ErrorResponse { response } => Some(response.code()),
+            _ => None,
+        }
+    }
+
+    /// Whether the same request may succeed when sent again, possibly after reconnecting:
+    /// connection errors, requoted prices, throttling, timeouts and internal errors of the server.
+    pub fn is_retryable(&self) -> bool {
+        self.is_connection_error()
+            || matches!(self, Error::RequestTimeout)
+            || self.error_code().is_some_and(|code| {
+                matches!(
+                    code,
+                    XApiErrorCode::OffQuotes
+                        | XApiErrorCode::PriceChanged
+                        | XApiErrorCode::RequestTooFrequent
+                        | XApiErrorCode::TooManyTradeRequests
+                        | XApiErrorCode::TooManyInstrumentTradeRequests
+                        | XApiErrorCode::TradingTimeout
+                        | XApiErrorCode::RequestTimedOut
+                        | XApiErrorCode::SystemOverloaded
+                        | XApiErrorCode::InternalError
+                        | XApiErrorCode::InternalErrorBe202
+                        | XApiErrorCode::InternalErrorBe203
+                        | XApiErrorCode::InternalErrorEx007
+                        | XApiErrorCode::InternalErrorEx011
+                        | XApiErrorCode::InternalErrorSe199
+                )
+            })
+    }
+
+    /// Whether the credentials or the session were refused.
+    pub fn is_auth_error(&self) -> bool {
+        self.error_code().is_some_and(|code| {
+            matches!(
+                code,
+                XApiErrorCode::LoginDisabled
+                    | XApiErrorCode::InvalidCredentials
+                    | XApiErrorCode::NotLoggedIn
+                    | XApiErrorCode::InvalidToken
+                    | XApiErrorCode::SessionTimedOut
+                    | XApiErrorCode::LoginIncorrect
+                    | XApiErrorCode::NoAccess
+                    | XApiErrorCode::LoginAttemptsLimited
+                    | XApiErrorCode::LoginBlacklisted
+            )
+        })
+    }
+
+    /// Whether a trade transaction was refused, by the server or by `safe` mode.
+    pub fn is_trading_rejection(&self) -> bool {
+        matches!(self, Error::TradingIsDisabled)
+            || self.error_code().is_some_and(|code| {
+                matches!(
+                    code,
+                    XApiErrorCode::InvalidPrice
+                        | XApiErrorCode::InvalidSlTp
+                        | XApiErrorCode::InvalidVolume
+                        | XApiErrorCode::MarketClosed
+                        | XApiErrorCode::ModificationDenied
+                        | XApiErrorCode::NotEnoughMoney
+                        | XApiErrorCode::OffQuotes
+                        | XApiErrorCode::OppositePositionsProhibited
+                        | XApiErrorCode::ShortPositionsProhibited
+                        | XApiErrorCode::PriceChanged
+                        | XApiErrorCode::TooManyTradeRequests
+                        | XApiErrorCode::TradingDisabled
+                        | XApiErrorCode::TradingTimeout
+                        | XApiErrorCode::SymbolNotTradable
+                        | XApiErrorCode::PendingOrderCannotBeClosed
+                        | XApiErrorCode::OrderAlreadyClosed
+                )
+            })
+    }
+
+    /// Returns an equivalent error, for reporting one failure to many receivers.
+    /// Errors, which cannot be copied, are replicated as `ConnectionClosed`.
+    pub(crate) fn replicate(&self) -> Error {
+        match self {
+            Error::ErrorResponse { response } => Error::ErrorResponse { response: response.clone() },
+            Error::ConnectionTimeout => Error::ConnectionTimeout,
+            Error::RequestTimeout => Error::RequestTimeout,
+            Error::NoDataReceived => Error::NoDataReceived,
+            Error::StaleConnection => Error::StaleConnection,
+            _ => Error::ConnectionClosed,
+        }
+    }
+}
+
+macro_rules! error_codes {
+    ($($(#[$doc:meta])* $variant:ident = $code:literal,)*) => {
+        /// Error code of an `ErrorResponse`, as documented by xAPI.
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub enum XApiErrorCode {
+            $($(#[$doc])* $variant,)*
+            /// Code not documented, or not known to this library.
+            Unknown(String),
+        }
+
+        impl XApiErrorCode {
+            /// Returns the raw code, e.g. `BE005`.
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $(XApiErrorCode::$variant => $code,)*
+                    XApiErrorCode::Unknown(code) => code,
+                }
+            }
+        }
+
+        impl From<&str> for XApiErrorCode {
+            fn from(code: &str) -> Self {
+                match code {
+                    $($code => XApiErrorCode::$variant,)*
+                    _ => XApiErrorCode::Unknown(String::from(code)),
+                }
+            }
+        }
+    };
+}
+
+error_codes! {
+    /// Invalid price
+    InvalidPrice = "BE001",
+    /// Invalid StopLoss or TakeProfit
+    InvalidSlTp = "BE002",
+    /// Invalid volume
+    InvalidVolume = "BE003",
+    /// Login disabled
+    LoginDisabled = "BE004",
+    /// Invalid login or password
+    InvalidCredentials = "BE005",
+    /// Market for instrument is closed
+    MarketClosed = "BE006",
+    /// Mismatched parameters
+    MismatchedParameters = "BE007",
+    /// Modification is denied
+    ModificationDenied = "BE008",
+    /// Not enough money on account to perform trade
+    NotEnoughMoney = "BE009",
+    /// Off quotes
+    OffQuotes = "BE010",
+    /// Opposite positions prohibited
+    OppositePositionsProhibited = "BE011",
+    /// Short positions prohibited
+    ShortPositionsProhibited = "BE012",
+    /// Price has changed
+    PriceChanged = "BE013",
+    /// Request too frequent, the server side rate limit
+    RequestTooFrequent = "BE014",
+    /// Too many trade requests
+    TooManyTradeRequests = "BE016",
+    /// Too many trade requests, on the instrument
+    TooManyInstrumentTradeRequests = "BE017",
+    /// Trading on instrument disabled
+    TradingDisabled = "BE018",
+    /// Trading timeout
+    TradingTimeout = "BE019",
+    /// Symbol does not exist for given account
+    SymbolNotAvailable = "BE094",
+    /// Account cannot trade on given symbol
+    SymbolNotTradable = "BE095",
+    /// Pending order cannot be closed, it must be deleted
+    PendingOrderCannotBeClosed = "BE096",
+    /// Cannot close already closed order
+    OrderAlreadyClosed = "BE097",
+    /// No such transaction
+    NoSuchTransaction = "BE098",
+    /// Unknown instrument symbol
+    UnknownSymbol = "BE101",
+    /// Unknown transaction type
+    UnknownTransactionType = "BE102",
+    /// User is not logged
+    NotLoggedIn = "BE103",
+    /// Method does not exist
+    MethodDoesNotExist = "BE104",
+    /// Incorrect period given
+    IncorrectPeriod = "BE105",
+    /// Missing data
+    MissingData = "BE106",
+    /// Incorrect command format
+    IncorrectCommandFormat = "BE110",
+    /// Symbol does not exist
+    SymbolDoesNotExist = "BE115",
+    /// Symbol does not exist
+    SymbolNotFound = "BE116",
+    /// Invalid token
+    InvalidToken = "BE117",
+    /// User already logged
+    AlreadyLoggedIn = "BE118",
+    /// Session timed out
+    SessionTimedOut = "BE200",
+    /// Invalid parameters
+    InvalidRequestParameters = "BE201",
+    /// Internal error
+    InternalErrorBe202 = "BE202",
+    /// Internal error
+    InternalErrorBe203 = "BE203",
+    /// Invalid parameters
+    InvalidParameters = "EX000",
+    /// Internal error
+    InternalError = "EX001",
+    /// Request timed out
+    RequestTimedOut = "EX002",
+    /// Login credentials are incorrect, or the login is not allowed to use the application
+    LoginIncorrect = "EX003",
+    /// Internal error, system overloaded
+    SystemOverloaded = "EX004",
+    /// No access
+    NoAccess = "EX005",
+    /// Login credentials are incorrect, the account will be disabled after further failed attempts
+    LoginAttemptsLimited = "EX006",
+    /// Internal error
+    InternalErrorEx007 = "EX007",
+    /// Data limit exceeded
+    DataLimitExceeded = "EX008",
+    /// Login is on the black list
+    LoginBlacklisted = "EX009",
+    /// Not allowed to execute the command
+    CommandNotAllowed = "EX010",
+    /// Internal error
+    InternalErrorEx011 = "EX011",
+    /// Internal error
+    InternalErrorSe199 = "SE199",
+}
+
+impl std::fmt::Display for XApiErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_response(code: &str) -> Error {
+        let response = ErrorResponse { error_code: String::from(code), ..Default::default() };
+        Error::ErrorResponse { response }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        assert_eq!(XApiErrorCode::from("BE005"), XApiErrorCode::InvalidCredentials);
+        assert_eq!(
+            XApiErrorCode::from("BE999"),
+            XApiErrorCode::Unknown(String::from("BE999"))
+        );
+        assert_eq!(XApiErrorCode::MarketClosed.as_str(), "BE006");
+        assert_eq!(XApiErrorCode::from("BE999").to_string(), "BE999");
+        assert_eq!(
+            error_response("BE009").error_code(),
+            Some(XApiErrorCode::NotEnoughMoney)
+        );
+        assert_eq!(Error::ConnectionClosed.error_code(), None);
+
+        for code in ["BE017", "BE116", "BE117", "BE200", "BE201", "BE202", "BE203", "SE199"] {
+            assert_eq!(XApiErrorCode::from(code).as_str(), code);
+        }
+        for code in 2..=11 {
+            let code = format!("EX{:03}", code);
+            assert!(!matches!(XApiErrorCode::from(code.as_str()), XApiErrorCode::Unknown(_)));
+            assert_eq!(XApiErrorCode::from(code.as_str()).as_str(), code);
+        }
+    }
+
+    #[test]
+    fn test_error_classification() {
+        assert!(error_response("BE014").is_retryable());
+        assert!(Error::ConnectionTimeout.is_retryable());
+        assert!(Error::RequestTimeout.is_retryable());
+        assert!(!Error::RequestTimeout.is_connection_error());
+        assert!(!error_response("BE009").is_retryable());
+        assert!(!error_response("BE999").is_retryable());
+        assert!(error_response("EX002").is_retryable());
+        assert!(error_response("EX004").is_retryable());
+        assert!(!error_response("EX003").is_retryable());
+        assert!(!error_response("EX008").is_retryable());
+        assert!(!error_response("EX010").is_retryable());
+
+        assert!(error_response("BE005").is_auth_error());
+        assert!(!error_response("BE006").is_auth_error());
+        assert!(error_response("EX003").is_auth_error());
+        assert!(error_response("EX006").is_auth_error());
+        assert!(error_response("EX009").is_auth_error());
+        assert!(error_response("BE117").is_auth_error());
+        assert!(!error_response("EX002").is_auth_error());
+        assert!(!error_response("EX004").is_auth_error());
+        assert!(!error_response("EX010").is_auth_error());
+
+        assert!(error_response("BE006").is_trading_rejection());
+        assert!(Error::TradingIsDisabled.is_trading_rejection());
+        assert!(!error_response("BE005").is_trading_rejection());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::data::*;
+use crate::error::Error;
+use crate::stream::{RecordReceiver, Stream, Subscription};
+
+use futures::stream::BoxStream;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Stream record type, which may be received through a `SubscriptionHandle`.
+pub trait StreamRecord: Sized {
+    /// Extracts the record, if it is of this type.
+    fn from_record(record: Record) -> Option<Self>;
+
+    /// Returns the symbol of the record, for subscriptions limited to one symbol.
+    fn symbol(&self) -> Option<&str> {
+        None
+    }
+}
+
+macro_rules! stream_record {
+    ($type:ident) => {
+        impl StreamRecord for $type {
+            fn from_record(record: Record) -> Option<Self> {
+                match record {
+                    Record::$type(record) => Some(record),
+                    _ => None,
+                }
+            }
+        }
+    };
+    ($type:ident, symbol) => {
+        impl StreamRecord for $type {
+            fn from_record(record: Record) -> Option<Self> {
+                match record {
+                    Record::$type(record) => Some(record),
+                    _ => None,
+                }
+            }
+
+            fn symbol(&self) -> Option<&str> {
+                Some(&self.symbol)
+            }
+        }
+    };
+}
+
+stream_record!(Balance);
+stream_record!(Candle, symbol);
+stream_record!(KeepAlive);
+stream_record!(News);
+stream_record!(Profit);
+stream_record!(Tick, symbol);
+stream_record!(Trade);
+stream_record!(TradeStatus);
+
+pub type BalanceSubscription = SubscriptionHandle<Balance>;
+pub type CandleSubscription = SubscriptionHandle<Candle>;
+pub type KeepAliveSubscription = SubscriptionHandle<KeepAlive>;
+pub type NewsSubscription = SubscriptionHandle<News>;
+pub type ProfitSubscription = SubscriptionHandle<Profit>;
+pub type TickSubscription = SubscriptionHandle<Tick>;
+pub type TradeSubscription = SubscriptionHandle<Trade>;
+pub type TradeStatusSubscription = SubscriptionHandle<TradeStatus>;
+
+/// Handle of a stream subscription, created by one of the `Stream::subscribe_*` methods.
+///
+/// Receives only the records of its own subscription. Handles of the same subscription share
+/// one server side subscription, which is stopped when the last handle is dropped.
+#[derive(Debug)]
+pub struct SubscriptionHandle<T: StreamRecord> {
+    stream: Stream,
+    subscription: Subscription,
+    receiver: RecordReceiver,
+    record: PhantomData<fn() -> T>,
+}
+
+impl<T: StreamRecord> SubscriptionHandle<T> {
+    pub(crate) fn new(stream: Stream, subscription: Subscription, receiver: RecordReceiver) -> Self {
+        SubscriptionHandle { stream, subscription, receiver, record: PhantomData }
+    }
+
+    pub fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+
+    /// Waits for the next record of the subscription.
+    pub async fn recv(&mut self) -> Result<T, Error> {
+        loop {
+            let record = match self.receiver.recv().await {
+                Ok(record) => record,
+                Err(Error::UnknownRecord { .. }) => continue,
+                Err(err) => return Err(err),
+            };
+            let Some(record) = T::from_record(record) else {
+                continue;
+            };
+            match (self.subscription.symbol(), record.symbol()) {
+                (Some(expected), Some(symbol)) if expected != symbol => continue,
+                _ => return Ok(record),
+            }
+        }
+    }
+}
+
+impl<T: StreamRecord + Send + 'static> SubscriptionHandle<T> {
+    /// Converts the handle into a `futures::Stream` of its records.
+    pub fn into_stream(self) -> RecordStream<T> {
+        let records = futures::stream::unfold(Some(self), |handle| async move {
+            let mut handle = handle?;
+            match handle.recv().await {
+                Err(err) if err.is_connection_error() => Some((Err(err), None)),
+                result => Some((result, Some(handle))),
+            }
+        });
+        RecordStream { records: Box::pin(records) }
+    }
+}
+
+impl<T: StreamRecord> Drop for SubscriptionHandle<T> {
+    fn drop(&mut self) {
+        self.stream.release_and_stop(&self.subscription);
+    }
+}
+
+/// `futures::Stream` of records of one subscription, e.g. created by `Stream::ticks`.
+///
+/// Ends after yielding a connection error. The subscription is released when the stream is dropped.
+pub struct RecordStream<T> {
+    records: BoxStream<'static, Result<T, Error>>,
+}
+
+impl<T> std::fmt::Debug for RecordStream<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RecordStream").finish_non_exhaustive()
+    }
+}
+
+impl<T> futures::Stream for RecordStream<T> {
+    type Item = Result<T, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.records.as_mut().poll_next(cx)
+    }
+}
@@ -1,39 +1,138 @@
+#![allow(clippy::result_large_err)]
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls-tls")))]
+compile_error!("Either the `native-tls` or the `rustls-tls` feature must be enabled");
+
+mod cassette;
+mod command;
+mod config;
 mod connection;
 mod credentials;
 mod data;
+#[cfg(feature = "chrono")]
+mod datetime;
+#[cfg(feature = "rust_decimal")]
+mod decimal;
 mod enums;
 mod error;
+mod handle;
+mod limiter;
+mod metrics;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+mod proxy;
+mod redact;
 mod socket;
+mod state;
 mod stream;
+mod supervisor;
+mod tls;
 
-use std::borrow::Cow;
-
+pub use cassette::{Cassette, Channel, Direction, Frame, Recorder};
+pub use config::{ConnectionConfig, XApiBuilder};
 pub use credentials::Credentials;
 pub use data::*;
 pub use enums::*;
-pub use error::Error;
+pub use error::{Error, XApiErrorCode};
+pub use handle::*;
+pub use limiter::{LimiterStats, RateLimiter, RateLimiterConfig};
+pub use metrics::Metrics;
+pub use proxy::{Proxy, ProxyAuth};
 pub use socket::Socket;
-pub use stream::Stream;
+pub use state::ConnectionState;
+pub use stream::{Heartbeat, RecordReceiver, Stream, Subscription};
+pub use supervisor::{Event, Supervisor};
+pub use tls::TlsConfig;
+
+use state::StateReporter;
+use tokio::sync::watch;
 
 #[derive(Debug, Clone)]
 pub struct XApi {
     pub socket: Socket,
     pub stream: Stream,
+    state: StateReporter,
 }
 
-pub async fn connect(credentials: &Credentials) -> Result<XApi, Error> {
-    let mut host = Cow::Borrowed(&credentials.host);
-    if !host.starts_with("wss://") && !host.starts_with("ws://") {
-        host.to_mut().insert_str(0, "wss://");
+impl XApi {
+    pub fn builder(credentials: &Credentials) -> XApiBuilder {
+        XApiBuilder::new(credentials)
+    }
+
+    /// Returns a receiver of the connection state changes.
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// Stops stream subscriptions, logs out and closes both connections.
+    pub async fn close(&self) -> Result<(), Error> {
+        let stream = self.stream.close().await;
+        let socket = self.socket.close().await;
+        self.state.set(ConnectionState::Closed);
+        stream.and(socket)
     }
+}
 
-    let socket_url = format!("{}/{}", &host, &credentials.type_);
-    let stream_url = format!("{}/{}Stream", &host, &credentials.type_);
+pub async fn connect(credentials: &Credentials) -> Result<XApi, Error> {
+    connect_with_config(credentials, &ConnectionConfig::default()).await
+}
 
-    let socket = Socket::connect(&socket_url, credentials.safe).await?;
+pub async fn connect_with_config(credentials: &Credentials, config: &ConnectionConfig) -> Result<XApi, Error> {
+    connect_reporting(credentials, config, StateReporter::new()).await
+}
+
+/// Connects, reporting the progress and then the connection health to the given state channel.
+pub(crate) async fn connect_reporting(
+    credentials: &Credentials,
+    config: &ConnectionConfig,
+    state: StateReporter,
+) -> Result<XApi, Error> {
+    let (socket_url, stream_url) = config.urls(credentials);
+
+    state.set(ConnectionState::Connecting);
+    let socket = Socket::connect_with_config(&socket_url, credentials.safe, config).await?;
     let login = socket.login(&credentials.account_id, &credentials.password).await?;
+    state.set(ConnectionState::LoggedIn);
+
+    let stream = Stream::connect_with_config(&stream_url, login.stream_session_id, config).await?;
+    state.spawn_monitor(
+        socket.health(),
+        stream.health(),
+        stream.heartbeats(),
+        config.heartbeat_timeout,
+    );
+
+    Ok(XApi { socket, stream, state })
+}
 
-    let stream = Stream::connect(&stream_url, login.stream_session_id).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Channel, MockServer};
+    use tokio::time::Duration;
 
-    Ok(XApi { socket, stream })
+    #[tokio::test]
+    async fn test_close() {
+        let server = MockServer::start().await.unwrap();
+        let x = connect(&server.credentials()).await.unwrap();
+        x.stream.get_candles("EURUSD").await.unwrap();
+
+        let listener = tokio::spawn({
+            let stream = x.stream.clone();
+            async move { stream.listen().await }
+        });
+
+        x.close().await.unwrap();
+        assert!(matches!(listener.await.unwrap(), Err(Error::ConnectionClosed)));
+
+        let stop = server.wait_for("stopCandles", Duration::from_secs(5)).await.unwrap();
+        assert_eq!(stop.channel, Channel::Stream);
+        assert_eq!(stop.json["symbol"], "EURUSD");
+        assert!(server.wait_for("logout", Duration::from_secs(5)).await.is_some());
+
+        assert!(matches!(x.socket.get_version().await, Err(Error::ConnectionClosed)));
+        assert!(matches!(x.stream.get_balance().await, Err(Error::ConnectionClosed)));
+        assert!(matches!(x.stream.listen().await, Err(Error::ConnectionClosed)));
+        assert!(x.close().await.is_ok());
+    }
 }
@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tokio::time::{sleep, Duration, Instant};
+
+/// Rate limiter configuration.
+///
+/// xAPI expects requests to be sent in `interval` gaps. The rule may be broken, but the connection
+/// is dropped after 6 consecutive violations. The defaults never violate the rule.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimiterConfig {
+    /// Minimal gap between requests, and the token refill period.
+    pub interval: Duration,
+    /// Number of requests, which may be sent at once (bucket capacity).
+    pub burst: u32,
+    /// Maximal number of consecutive requests sent sooner than `interval` after the previous one.
+    pub max_violations: u32,
+}
+
+impl Default for RateLimiterConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(200),
+            burst: 1,
+            max_violations: 5,
+        }
+    }
+}
+
+/// Rate limiter statistics.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LimiterStats {
+    /// Number of requests passed through the limiter.
+    pub requests: u64,
+    /// Number of requests, which had to wait.
+    pub throttled: u64,
+    /// Total time spent waiting.
+    pub throttled_time: Duration,
+    /// Number of requests sent sooner than `interval` after the previous one.
+    pub violations: u64,
+    /// Current number of consecutive violations.
+    pub streak: u32,
+}
+
+#[derive(Debug)]
+struct State {
+    tokens: f64,
+    refilled: Instant,
+    last_request: Option<Instant>,
+    skip: bool,
+    stats: LimiterStats,
+}
+
+/// Token bucket rate limiter. Clones share the same bucket, so one limiter may pace many connections.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: RateLimiterConfig,
+    state: Arc<Mutex<State>>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimiterConfig::default())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimiterConfig) -> RateLimiter {
+        let config = RateLimiterConfig { burst: config.burst.max(1), ..config };
+        let state = State {
+            tokens: config.burst as f64,
+            refilled: Instant::now(),
+            last_request: None,
+            skip: false,
+            stats: LimiterStats::default(),
+        };
+        RateLimiter { config, state: Arc::new(Mutex::new(state)) }
+    }
+
+    pub fn config(&self) -> RateLimiterConfig {
+        self.config
+    }
+
+    pub async fn stats(&self) -> LimiterStats {
+        self.state.lock().await.stats
+    }
+
+    /// Lets the next request through without waiting.
+    pub async fn skip(&self) {
+        self.state.lock().await.skip = true;
+    }
+
+    /// Waits until a request may be sent, and accounts for it. Returns the time waited.
+    pub async fn acquire(&self) -> Duration {
+        let interval = self.config.interval;
+        let mut state = self.state.lock().await;
+        self.refill(&mut state);
+
+        let mut wait = Duration::ZERO;
+        if !state.skip {
+            // Out of the burst, the gap is measured from the previous request, not to break the rule by rounding.
+            let gap = state.last_request.map(|last| last.elapsed());
+            if state.tokens < 1.0 {
+                wait = interval.mul_f64(1.0 - state.tokens);
+                wait = wait.max(gap.map_or(Duration::ZERO, |gap| interval.saturating_sub(gap)));
+            }
+            if let Some(gap) = gap {
+                let gap = gap + wait;
+                if gap < interval && state.stats.streak >= self.config.max_violations {
+                    wait += interval - gap;
+                }
+            }
+        }
+        state.skip = false;
+
+        if !wait.is_zero() {
+            sleep(wait).await;
+            state.stats.throttled += 1;
+            state.stats.throttled_time += wait;
+        }
+
+        let now = Instant::now();
+        self.refill_at(&mut state, now);
+        let violation = state
+            .last_request
+            .is_some_and(|last| now.duration_since(last) < interval);
+        if violation {
+            state.stats.violations += 1;
+            state.stats.streak += 1;
+        } else {
+            state.stats.streak = 0;
+        }
+
+        state.tokens = (state.tokens - 1.0).max(0.0);
+        state.last_request = Some(now);
+        state.stats.requests += 1;
+        wait
+    }
+
+    fn refill(&self, state: &mut State) {
+        self.refill_at(state, Instant::now());
+    }
+
+    /// Adds the tokens accrued until `now`. The refill and the request share the instant,
+    /// so the next refill is measured from the request.
+    fn refill_at(&self, state: &mut State, now: Instant) {
+        let elapsed = now.duration_since(state.refilled);
+        let tokens = elapsed.as_secs_f64() / self.config.interval.as_secs_f64();
+        state.tokens = (state.tokens + tokens).min(self.config.burst as f64);
+        state.refilled = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn elapsed(limiter: &RateLimiter, requests: usize) -> Duration {
+        let start = Instant::now();
+        for _ in 0..requests {
+            limiter.acquire().await;
+        }
+        start.elapsed()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_default_paces_requests() {
+        let limiter = RateLimiter::default();
+        let elapsed = elapsed(&limiter, 4).await;
+        assert!(elapsed >= Duration::from_millis(600));
+
+        let stats = limiter.stats().await;
+        assert_eq!(stats.requests, 4);
+        assert_eq!(stats.throttled, 3);
+        assert_eq!(stats.violations, 0);
+        assert!(stats.throttled_time >= Duration::from_millis(599));
+    }
+
+    #[tokio::test]
+    async fn test_default_never_violates_in_real_time() {
+        let limiter = RateLimiter::default();
+        let mut sent = Vec::new();
+        for _ in 0..5 {
+            limiter.acquire().await;
+            sent.push(Instant::now());
+        }
+        for gap in sent.windows(2).map(|pair| pair[1] - pair[0]) {
+            assert!(gap >= Duration::from_millis(200), "{:?}", gap);
+        }
+        assert_eq!(limiter.stats().await.violations, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst() {
+        let limiter = RateLimiter::new(RateLimiterConfig { burst: 3, ..Default::default() });
+        assert!(elapsed(&limiter, 3).await < Duration::from_millis(1));
+        assert!(elapsed(&limiter, 1).await >= Duration::from_millis(199));
+
+        let stats = limiter.stats().await;
+        assert_eq!(stats.throttled, 1);
+        assert_eq!(stats.violations, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_violation_streak_is_bounded() {
+        let limiter = RateLimiter::new(RateLimiterConfig { burst: 20, max_violations: 5, ..Default::default() });
+        assert!(elapsed(&limiter, 6).await < Duration::from_millis(1));
+        assert_eq!(limiter.stats().await.streak, 5);
+
+        assert!(elapsed(&limiter, 1).await >= Duration::from_millis(199));
+        assert_eq!(limiter.stats().await.streak, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_skip() {
+        let limiter = RateLimiter::default();
+        limiter.acquire().await;
+        limiter.skip().await;
+        assert!(elapsed(&limiter, 1).await < Duration::from_millis(1));
+        assert_eq!(limiter.stats().await.violations, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shared_between_clones() {
+        let limiter = RateLimiter::default();
+        let clone = limiter.clone();
+        limiter.acquire().await;
+        assert!(elapsed(&clone, 1).await >= Duration::from_millis(199));
+        assert_eq!(limiter.stats().await.requests, 2);
+    }
+}
@@ -0,0 +1,102 @@
+use crate::data::Record;
+use crate::error::Error;
+
+use tokio::time::Duration;
+
+/// Instrumentation hooks, set with `ConnectionConfig::metrics`. All methods default to no-op,
+/// so an implementation overrides only what it needs, e.g. to feed a metrics exporter.
+pub trait Metrics: Send + Sync + std::fmt::Debug {
+    /// Time between sending a socket command and receiving its response.
+    fn request_latency(&self, _command: &str, _latency: Duration) {}
+
+    /// Failed socket command, or stream command. See `Error::kind` for the variant name.
+    fn request_error(&self, _command: &str, _error: &Error) {}
+
+    /// Time a request was held back by the rate limiter.
+    fn throttled(&self, _wait: Duration) {}
+
+    /// Received stream record. See `Record::command` for the record kind.
+    fn record(&self, _record: &Record) {}
+
+    /// Connection error, which stopped receiving on the socket or the stream.
+    fn connection_error(&self, _error: &Error) {}
+
+    /// Reconnection made by a `Supervisor`.
+    fn reconnected(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConnectionConfig;
+    use crate::mock::{MockServer, Reply};
+    use crate::supervisor::{Event, Supervisor};
+
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Default)]
+    struct Counters {
+        latencies: Mutex<Vec<String>>,
+        errors: Mutex<HashMap<&'static str, usize>>,
+        throttled: Mutex<Duration>,
+        records: Mutex<HashMap<&'static str, usize>>,
+        reconnects: Mutex<usize>,
+    }
+
+    impl Metrics for Counters {
+        fn request_latency(&self, command: &str, _latency: Duration) {
+            self.latencies.lock().unwrap().push(String::from(command));
+        }
+
+        fn request_error(&self, _command: &str, error: &Error) {
+            *self.errors.lock().unwrap().entry(error.kind()).or_default() += 1;
+        }
+
+        fn throttled(&self, wait: Duration) {
+            *self.throttled.lock().unwrap() += wait;
+        }
+
+        fn record(&self, record: &Record) {
+            *self.records.lock().unwrap().entry(record.command()).or_default() += 1;
+        }
+
+        fn reconnected(&self) {
+            *self.reconnects.lock().unwrap() += 1;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reported_metrics() {
+        let server = MockServer::start().await.unwrap();
+        server.once("getVersion", Reply::error("EX001", "Internal error"));
+        let counters = Arc::new(Counters::default());
+        let config = ConnectionConfig { metrics: Some(counters.clone()), ..Default::default() };
+
+        let supervisor = Supervisor::connect_with_config(&server.credentials(), &config)
+            .await
+            .unwrap()
+            .with_backoff(Duration::from_millis(10), Duration::from_millis(10));
+        let socket = supervisor.socket().await;
+        socket.get_symbol("EURUSD").await.unwrap();
+        assert!(socket.get_version().await.is_err());
+
+        supervisor.stream().await.get_keep_alive().await.unwrap();
+        server.wait_for("getKeepAlive", Duration::from_secs(5)).await.unwrap();
+        server.push("keepAlive", json!({ "timestamp": 1 }));
+        assert!(matches!(supervisor.listen().await, Ok(Event::Record(_))));
+
+        server.drop_connections();
+        assert!(matches!(supervisor.listen().await, Ok(Event::Reconnected)));
+
+        assert_eq!(
+            counters.latencies.lock().unwrap()[..3],
+            ["login", "getSymbol", "getVersion"]
+        );
+        assert_eq!(counters.errors.lock().unwrap()["ErrorResponse"], 1);
+        assert!(*counters.throttled.lock().unwrap() > Duration::ZERO);
+        assert_eq!(counters.records.lock().unwrap()["keepAlive"], 1);
+        assert_eq!(*counters.reconnects.lock().unwrap(), 1);
+    }
+}
@@ -0,0 +1,350 @@
+//! In-process mock of the xAPI server, for testing without a real endpoint.
+//!
+//! The server speaks both protocols used by the library: the request/response socket protocol at `/{type}`
+//! and the streaming protocol at `/{type}Stream`. Responses are scripted per command with `MockServer::on`
+//! and `MockServer::once`, stream records are pushed with `MockServer::push`.
+
+use crate::credentials::Credentials;
+use crate::data::Symbol;
+
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, Notify};
+use tokio::task::JoinHandle;
+use tokio::time::{timeout, Duration};
+
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+/// Reply of the mock server to a socket command.
+#[derive(Debug, Clone)]
+pub enum Reply {
+    /// Successful response, with the given value as `returnData`.
+    Data(Value),
+    /// Response sent as is.
+    Json(Value),
+    /// Error response with the given `errorCode` and `errorDescr`.
+    Error { code: String, descr: String },
+    /// No response at all.
+    Silence,
+    /// The connection is dropped, without a Close frame.
+    Drop,
+}
+
+impl Reply {
+    pub fn error(code: &str, descr: &str) -> Reply {
+        Reply::Error { code: String::from(code), descr: String::from(descr) }
+    }
+}
+
+/// Connection kind a command was received on.
+pub use crate::cassette::Channel;
+
+/// Command received by the mock server.
+#[derive(Debug, Clone)]
+pub struct Received {
+    pub channel: Channel,
+    pub command: String,
+    pub json: Value,
+}
+
+#[derive(Debug, Clone)]
+enum Control {
+    Push(String),
+    Drop,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    replies: HashMap<String, Reply>,
+    once: HashMap<String, VecDeque<Reply>>,
+    received: Vec<Received>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<State>,
+    received: Notify,
+    next_order: AtomicI64,
+}
+
+/// Mock xAPI server, listening on a local port until dropped.
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    control: broadcast::Sender<Control>,
+    acceptor: JoinHandle<()>,
+}
+
+impl MockServer {
+    pub async fn start() -> std::io::Result<MockServer> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            received: Notify::new(),
+            next_order: AtomicI64::new(1),
+        });
+        let (control, _) = broadcast::channel(1024);
+
+        let acceptor = {
+            let shared = shared.clone();
+            let control = control.clone();
+            tokio::spawn(async move {
+                while let Ok((tcp, _)) = listener.accept().await {
+                    tokio::spawn(serve(tcp, shared.clone(), control.subscribe()));
+                }
+            })
+        };
+
+        Ok(MockServer { addr, shared, control, acceptor })
+    }
+
+    /// Returns the server url, e.g. `ws://127.0.0.1:12345`.
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    /// Returns credentials pointing at the mock server.
+    pub fn credentials(&self) -> Credentials {
+        Credentials {
+            account_id: String::from("mock"),
+            password: String::from("mock"),
+            host: self.url(),
+            type_: String::from("demo"),
+            safe: false,
+        }
+    }
+
+    /// Sets the reply for every following occurrence of the command.
+    pub fn on(&self, command: &str, reply: Reply) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.replies.insert(String::from(command), reply);
+    }
+
+    /// Queues a reply for a single occurrence of the command. Takes precedence over `on`.
+    pub fn once(&self, command: &str, reply: Reply) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.once.entry(String::from(command)).or_default().push_back(reply);
+    }
+
+    /// Pushes a record to all connected streams, e.g. `push("candle", candle)`.
+    pub fn push<T: Serialize>(&self, command: &str, data: T) {
+        let record = json!({ "command": command, "data": data });
+        self.control.send(Control::Push(record.to_string())).ok();
+    }
+
+    /// Drops all open connections, without a Close frame.
+    pub fn drop_connections(&self) {
+        self.control.send(Control::Drop).ok();
+    }
+
+    /// Returns all commands received so far.
+    pub fn received(&self) -> Vec<Received> {
+        self.shared.state.lock().unwrap().received.clone()
+    }
+
+    /// Waits until the command is received, returning its first occurrence.
+    pub async fn wait_for(&self, command: &str, duration: Duration) -> Option<Received> {
+        let wait = async {
+            loop {
+                let notified = self.shared.received.notified();
+                if let Some(received) = self.received().into_iter().find(|r| r.command == command) {
+                    return received;
+                }
+                notified.await;
+            }
+        };
+        timeout(duration, wait).await.ok()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.acceptor.abort();
+    }
+}
+
+async fn serve(tcp: TcpStream, shared: Arc<Shared>, mut control: broadcast::Receiver<Control>) {
+    let mut path = String::new();
+    let callback = |request: &Request, response: Response| {
+        path = request.uri().path().to_owned();
+        Ok(response)
+    };
+    let Ok(mut ws) = tokio_tungstenite::accept_hdr_async(tcp, callback).await else {
+        return;
+    };
+    let channel = match path.ends_with("Stream") {
+        true => Channel::Stream,
+        false => Channel::Socket,
+    };
+
+    loop {
+        tokio::select! {
+            message = ws.next() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => continue,
+                };
+                let Ok(json) = serde_json::from_str::<Value>(&text) else {
+                    continue;
+                };
+                let command = json["command"].as_str().unwrap_or_default().to_owned();
+
+                let reply = {
+                    let mut state = shared.state.lock().unwrap();
+                    state.received.push(Received { channel, command: command.clone(), json: json.clone() });
+                    match state.once.get_mut(&command).and_then(|queue| queue.pop_front()) {
+                        Some(reply) => Some(reply),
+                        None => state.replies.get(&command).cloned(),
+                    }
+                };
+                shared.received.notify_waiters();
+
+                if channel == Channel::Stream {
+                    match reply {
+                        Some(Reply::Drop) => return,
+                        _ => continue,
+                    }
+                }
+
+                let mut response = match reply.unwrap_or_else(|| default_reply(&shared, &command, &json)) {
+                    Reply::Data(data) => json!({ "status": true, "returnData": data }),
+                    Reply::Json(json) => json,
+                    Reply::Error { code, descr } => json!({ "status": false, "errorCode": code, "errorDescr": descr }),
+                    Reply::Silence => continue,
+                    Reply::Drop => return,
+                };
+                if let (Some(response), Some(tag)) = (response.as_object_mut(), json.get("customTag")) {
+                    response.insert(String::from("customTag"), tag.clone());
+                }
+                if ws.send(Message::Text(response.to_string())).await.is_err() {
+                    return;
+                }
+            }
+            control = control.recv() => match control {
+                Ok(Control::Push(record)) if channel == Channel::Stream => {
+                    if ws.send(Message::Text(record)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(Control::Push(_)) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Ok(Control::Drop) | Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+}
+
+fn default_reply(shared: &Shared, command: &str, json: &Value) -> Reply {
+    let arguments = &json["arguments"];
+    match command {
+        "login" => Reply::Json(json!({ "status": true, "streamSessionId": "mock-stream-session-id" })),
+        "logout" | "ping" => Reply::Json(json!({ "status": true })),
+        "getSymbol" => {
+            let symbol = Symbol {
+                symbol: arguments["symbol"].as_str().unwrap_or_default().to_owned(),
+                ..Default::default()
+            };
+            Reply::Data(serde_json::to_value(symbol).unwrap_or_default())
+        }
+        "tradeTransaction" => {
+            let order = shared.next_order.fetch_add(1, Ordering::Relaxed);
+            Reply::Data(json!({ "order": order }))
+        }
+        "tradeTransactionStatus" => Reply::Data(json!({
+            "ask": null,
+            "bid": null,
+            "customComment": "",
+            "message": null,
+            "order": arguments["order"],
+            "price": null,
+            "requestStatus": 3,
+        })),
+        _ => Reply::error("EX000", &format!("No reply scripted for {}", command)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::{Record, Transaction};
+    use crate::enums::RequestStatus;
+    use crate::error::Error;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    #[tokio::test]
+    async fn test_login_and_default_replies() {
+        let server = MockServer::start().await.unwrap();
+        let x = crate::connect(&server.credentials()).await.unwrap();
+
+        let symbol = x.socket.get_symbol("EURUSD").await.unwrap();
+        assert_eq!(symbol.return_data.symbol, "EURUSD");
+
+        let order = x.socket.trade_transaction(Transaction::default()).await.unwrap();
+        let status = x
+            .socket
+            .trade_transaction_status(order.return_data.order)
+            .await
+            .unwrap();
+        assert_eq!(status.return_data.order, order.return_data.order);
+        assert!(matches!(status.return_data.request_status, RequestStatus::Accepted));
+
+        let login = server.wait_for("login", WAIT).await.unwrap();
+        assert_eq!(login.channel, Channel::Socket);
+        assert_eq!(login.json["arguments"]["userId"], "mock");
+    }
+
+    #[tokio::test]
+    async fn test_scripted_error_response() {
+        let server = MockServer::start().await.unwrap();
+        server.once("getSymbol", Reply::error("BE115", "Invalid symbol"));
+        let x = crate::connect(&server.credentials()).await.unwrap();
+
+        match x.socket.get_symbol("UNKNOWN").await {
+            Err(Error::ErrorResponse { response }) => assert_eq!(response.error_code, "BE115"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(x.socket.get_symbol("EURUSD").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_pushed_records() {
+        let server = MockServer::start().await.unwrap();
+        let x = crate::connect(&server.credentials()).await.unwrap();
+
+        x.stream.get_keep_alive().await.unwrap();
+        let received = server.wait_for("getKeepAlive", WAIT).await.unwrap();
+        assert_eq!(received.channel, Channel::Stream);
+        assert_eq!(received.json["streamSessionId"], "mock-stream-session-id");
+
+        server.push("keepAlive", json!({ "timestamp": 1234 }));
+        match x.stream.listen().await.unwrap() {
+            Record::KeepAlive(keep_alive) => assert_eq!(keep_alive.timestamp, 1234),
+            other => panic!("unexpected record: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dropped_connection() {
+        let server = MockServer::start().await.unwrap();
+        server.on("getVersion", Reply::Drop);
+        let x = crate::connect(&server.credentials()).await.unwrap();
+
+        let err = x.socket.get_version().await.unwrap_err();
+        assert!(err.is_connection_error());
+
+        server.drop_connections();
+        let err = x.stream.listen().await.unwrap_err();
+        assert!(err.is_connection_error());
+    }
+}
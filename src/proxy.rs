@@ -0,0 +1,302 @@
+use crate::connection::io_error;
+use crate::error::Error;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Proxy, which the WebSocket connections are tunneled through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Proxy {
+    /// HTTP proxy supporting the `CONNECT` method, e.g. `Proxy::http("proxy.local:3128")`.
+    Http { addr: String, auth: Option<ProxyAuth> },
+    /// SOCKS5 proxy, e.g. `Proxy::socks5("proxy.local:1080")`. The target host is resolved by the proxy.
+    Socks5 { addr: String, auth: Option<ProxyAuth> },
+}
+
+/// Proxy credentials, sent as HTTP basic auth or SOCKS5 username/password.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ProxyAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl std::fmt::Debug for ProxyAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProxyAuth")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .finish()
+    }
+}
+
+impl Proxy {
+    pub fn http(addr: &str) -> Proxy {
+        Proxy::Http { addr: String::from(addr), auth: None }
+    }
+
+    pub fn socks5(addr: &str) -> Proxy {
+        Proxy::Socks5 { addr: String::from(addr), auth: None }
+    }
+
+    /// Sets the proxy credentials.
+    pub fn with_auth(self, username: &str, password: &str) -> Proxy {
+        let auth = Some(ProxyAuth {
+            username: String::from(username),
+            password: String::from(password),
+        });
+        match self {
+            Proxy::Http { addr, .. } => Proxy::Http { addr, auth },
+            Proxy::Socks5 { addr, .. } => Proxy::Socks5 { addr, auth },
+        }
+    }
+
+    /// Opens a tunnel to the given host.
+    pub(crate) async fn connect(&self, host: &str, port: u16) -> Result<TcpStream, Error> {
+        match self {
+            Proxy::Http { addr, auth } => {
+                let mut tcp = TcpStream::connect(addr).await.map_err(io_error)?;
+                http_connect(&mut tcp, host, port, auth.as_ref()).await?;
+                Ok(tcp)
+            }
+            Proxy::Socks5 { addr, auth } => {
+                let mut tcp = TcpStream::connect(addr).await.map_err(io_error)?;
+                socks5_connect(&mut tcp, host, port, auth.as_ref()).await?;
+                Ok(tcp)
+            }
+        }
+    }
+}
+
+fn rejected(reason: String) -> Error {
+    Error::ProxyError { reason }
+}
+
+/// Returns the length prefix of a SOCKS5 field, which holds at most 255 bytes.
+fn socks5_length(name: &str, value: &str) -> Result<u8, Error> {
+    u8::try_from(value.len()).map_err(|_| rejected(format!("SOCKS5 {} longer than 255 bytes", name)))
+}
+
+async fn http_connect(tcp: &mut TcpStream, host: &str, port: u16, auth: Option<&ProxyAuth>) -> Result<(), Error> {
+    let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+    if let Some(auth) = auth {
+        let credentials = base64::encode(format!("{}:{}", auth.username, auth.password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    request.push_str("\r\n");
+    tcp.write_all(request.as_bytes()).await.map_err(io_error)?;
+
+    // Read byte by byte, not to consume anything past the response header.
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() > 8192 {
+            return Err(rejected(String::from("Proxy response header too long")));
+        }
+        response.push(tcp.read_u8().await.map_err(io_error)?);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status = response.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some("200") => Ok(()),
+        _ => Err(rejected(format!("Proxy responded with: {}", status))),
+    }
+}
+
+async fn socks5_connect(tcp: &mut TcpStream, host: &str, port: u16, auth: Option<&ProxyAuth>) -> Result<(), Error> {
+    const VERSION: u8 = 5;
+    const NO_AUTH: u8 = 0;
+    const USERNAME_PASSWORD: u8 = 2;
+
+    let host_length = socks5_length("host", host)?;
+    let credentials = match auth {
+        Some(auth) => Some((
+            socks5_length("username", &auth.username)?,
+            socks5_length("password", &auth.password)?,
+        )),
+        None => None,
+    };
+    let method = match auth {
+        Some(_) => USERNAME_PASSWORD,
+        None => NO_AUTH,
+    };
+    tcp.write_all(&[VERSION, 1, method]).await.map_err(io_error)?;
+    let mut reply = [0u8; 2];
+    tcp.read_exact(&mut reply).await.map_err(io_error)?;
+    if reply != [VERSION, method] {
+        return Err(rejected(String::from("SOCKS5 authentication method not accepted")));
+    }
+
+    if let (Some(auth), Some((username_length, password_length))) = (auth, credentials) {
+        let mut request = vec![1, username_length];
+        request.extend_from_slice(auth.username.as_bytes());
+        request.push(password_length);
+        request.extend_from_slice(auth.password.as_bytes());
+        tcp.write_all(&request).await.map_err(io_error)?;
+        tcp.read_exact(&mut reply).await.map_err(io_error)?;
+        if reply[1] != 0 {
+            return Err(rejected(String::from("SOCKS5 authentication failed")));
+        }
+    }
+
+    let mut request = vec![VERSION, 1, 0, 3, host_length];
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    tcp.write_all(&request).await.map_err(io_error)?;
+
+    let mut reply = [0u8; 4];
+    tcp.read_exact(&mut reply).await.map_err(io_error)?;
+    if reply[1] != 0 {
+        return Err(rejected(format!("SOCKS5 connect failed with code {}", reply[1])));
+    }
+    let addr_len = match reply[3] {
+        1 => 4,
+        3 => tcp.read_u8().await.map_err(io_error)? as usize,
+        4 => 16,
+        atyp => return Err(rejected(format!("SOCKS5 unknown address type {}", atyp))),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    tcp.read_exact(&mut bound).await.map_err(io_error)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConnectionConfig;
+    use crate::mock::MockServer;
+
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+
+    /// Local HTTP CONNECT proxy, recording the request headers.
+    async fn http_proxy(headers: Arc<Mutex<String>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                let headers = headers.clone();
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    while !request.ends_with(b"\r\n\r\n") {
+                        request.push(client.read_u8().await.unwrap());
+                    }
+                    let request = String::from_utf8(request).unwrap();
+                    *headers.lock().unwrap() = request.clone();
+                    let target = request.split_whitespace().nth(1).unwrap().to_owned();
+                    let mut server = TcpStream::connect(target).await.unwrap();
+                    client
+                        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                        .await
+                        .unwrap();
+                    tokio::io::copy_bidirectional(&mut client, &mut server).await.ok();
+                });
+            }
+        });
+        addr
+    }
+
+    /// Local SOCKS5 proxy, accepting the given credentials only.
+    async fn socks5_proxy(username: &'static str, password: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((mut client, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut greeting = [0u8; 3];
+                    client.read_exact(&mut greeting).await.unwrap();
+                    client.write_all(&[5, 2]).await.unwrap();
+
+                    let field = |len| vec![0u8; len];
+                    let mut version = [0u8; 2];
+                    client.read_exact(&mut version).await.unwrap();
+                    let mut user = field(version[1] as usize);
+                    client.read_exact(&mut user).await.unwrap();
+                    let mut pass = field(client.read_u8().await.unwrap() as usize);
+                    client.read_exact(&mut pass).await.unwrap();
+                    let accepted = user == username.as_bytes() && pass == password.as_bytes();
+                    client
+                        .write_all(&[
+                            1,
+                            if accepted {
+                                0
+                            } else {
+                                1
+                            },
+                        ])
+                        .await
+                        .unwrap();
+                    if !accepted {
+                        return;
+                    }
+
+                    let mut request = [0u8; 5];
+                    client.read_exact(&mut request).await.unwrap();
+                    let mut host = field(request[4] as usize);
+                    client.read_exact(&mut host).await.unwrap();
+                    let port = client.read_u16().await.unwrap();
+                    let host = String::from_utf8(host).unwrap();
+                    let mut server = TcpStream::connect((host.as_str(), port)).await.unwrap();
+                    client.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).await.unwrap();
+                    tokio::io::copy_bidirectional(&mut client, &mut server).await.ok();
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_http_proxy_with_basic_auth() {
+        let server = MockServer::start().await.unwrap();
+        let headers = Arc::new(Mutex::new(String::new()));
+        let proxy = Proxy::http(&http_proxy(headers.clone()).await).with_auth("user", "secret");
+        let config = ConnectionConfig { proxy: Some(proxy), ..Default::default() };
+
+        let x = crate::connect_with_config(&server.credentials(), &config)
+            .await
+            .unwrap();
+        assert!(x.socket.ping().await.is_ok());
+        assert!(headers
+            .lock()
+            .unwrap()
+            .contains("Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n"));
+    }
+
+    #[tokio::test]
+    async fn test_socks5_proxy() {
+        let server = MockServer::start().await.unwrap();
+        let proxy = Proxy::socks5(&socks5_proxy("user", "secret").await);
+
+        let config = ConnectionConfig {
+            proxy: Some(proxy.clone().with_auth("user", "secret")),
+            ..Default::default()
+        };
+        let x = crate::connect_with_config(&server.credentials(), &config)
+            .await
+            .unwrap();
+        assert!(x.socket.ping().await.is_ok());
+        x.stream.get_keep_alive().await.unwrap();
+        assert!(server
+            .wait_for("getKeepAlive", std::time::Duration::from_secs(5))
+            .await
+            .is_some());
+
+        let config = ConnectionConfig {
+            proxy: Some(proxy.with_auth("user", "wrong")),
+            ..Default::default()
+        };
+        let err = crate::connect_with_config(&server.credentials(), &config)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::ProxyError { .. }));
+        assert!(!err.is_connection_error());
+
+        let config = ConnectionConfig {
+            proxy: Some(Proxy::socks5(&socks5_proxy("user", "secret").await).with_auth("user", &"x".repeat(256))),
+            ..Default::default()
+        };
+        let err = crate::connect_with_config(&server.credentials(), &config)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::ProxyError { reason } if reason.contains("password")));
+    }
+}
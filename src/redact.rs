@@ -0,0 +1,138 @@
+use serde_json::Value;
+
+/// Fields, whose values never leave the library in logs or cassettes.
+const SECRETS: [&str; 2] = ["password", "streamSessionId"];
+
+const MASK: &str = "***";
+
+/// Returns the JSON message with the secret fields masked. Other messages are returned as is.
+pub(crate) fn redact(message: &str) -> String {
+    if !SECRETS.iter().any(|secret| message.contains(secret)) {
+        return String::from(message);
+    }
+    match serde_json::from_str::<Value>(message) {
+        Ok(mut json) => {
+            mask(&mut json);
+            json.to_string()
+        }
+        Err(_) => String::from(message),
+    }
+}
+
+fn mask(json: &mut Value) {
+    match json {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                match SECRETS.contains(&key.as_str()) {
+                    true => *value = Value::from(MASK),
+                    false => mask(value),
+                }
+            }
+        }
+        Value::Array(array) => array.iter_mut().for_each(mask),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{Command, LoginArgs, StreamCommand};
+    use crate::credentials::Credentials;
+    use crate::mock::MockServer;
+
+    use log::{Log, Metadata, Record};
+    use std::sync::{Mutex, Once};
+
+    static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    /// Logger capturing the messages of this crate.
+    struct Capture;
+
+    impl Log for Capture {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.target().starts_with("xapi")
+        }
+
+        fn log(&self, record: &Record) {
+            if self.enabled(record.metadata()) {
+                LINES.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&Capture).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+    }
+
+    #[test]
+    fn test_redact() {
+        let message = r#"{"command":"login","arguments":{"userId":"123","password":"secret"}}"#;
+        assert_eq!(
+            redact(message),
+            r#"{"arguments":{"password":"***","userId":"123"},"command":"login"}"#
+        );
+        assert_eq!(redact(r#"{"command":"ping"}"#), r#"{"command":"ping"}"#);
+        assert_eq!(redact("not json, password"), "not json, password");
+    }
+
+    #[test]
+    fn test_redact_commands() {
+        let arguments = LoginArgs { user_id: "123", password: "top-secret-password" };
+        let login = serde_json::to_string(&Command::with_arguments("login", arguments)).unwrap();
+        assert!(!redact(&login).contains("top-secret-password"));
+
+        let command = StreamCommand {
+            command: "getKeepAlive",
+            stream_session_id: Some("session-id"),
+            arguments: (),
+        };
+        let command = serde_json::to_string(&command).unwrap();
+        assert!(!redact(&command).contains("session-id"));
+    }
+
+    #[tokio::test]
+    async fn test_no_secrets_in_debug() {
+        let server = MockServer::start().await.unwrap();
+        let credentials = Credentials {
+            password: String::from("top-secret-password"),
+            ..server.credentials()
+        };
+        let x = crate::connect(&credentials).await.unwrap();
+        x.stream.get_keep_alive().await.unwrap();
+
+        let debug = format!("{:?} {:?} {:?}", credentials, x, x.stream);
+        assert!(debug.contains("subscriptions: [KeepAlive]"), "{}", debug);
+        assert!(!debug.contains("top-secret-password"), "{}", debug);
+        assert!(!debug.contains("mock-stream-session-id"), "{}", debug);
+        x.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_no_secrets_in_logs() {
+        capture_logs();
+        let server = MockServer::start().await.unwrap();
+        let credentials = Credentials {
+            password: String::from("top-secret-password"),
+            ..server.credentials()
+        };
+        let x = crate::connect(&credentials).await.unwrap();
+        x.stream.get_keep_alive().await.unwrap();
+        x.socket.ping().await.unwrap();
+        x.close().await.unwrap();
+
+        let lines = LINES.lock().unwrap().clone();
+        assert!(lines.iter().any(|line| line.contains("login")));
+        assert!(lines.iter().any(|line| line.contains("getKeepAlive")));
+        for line in lines.iter() {
+            assert!(!line.contains("top-secret-password"), "{}", line);
+            assert!(!line.contains("mock-stream-session-id"), "{}", line);
+        }
+    }
+}
@@ -1,13 +1,14 @@
-use crate::connection::Connection;
+use crate::cassette::Channel;
+use crate::command::*;
+use crate::config::ConnectionConfig;
+use crate::connection::{Connection, KEEPALIVE_TAG};
 use crate::data::*;
 use crate::enums::*;
 use crate::error::Error;
+use crate::limiter::RateLimiter;
+use crate::state::Health;
 
-macro_rules! trim {
-    ($num:expr) => {
-        format!("{:.10}", $num).trim_end_matches('0').trim_end_matches('.')
-    };
-}
+use tokio::sync::watch;
 
 #[derive(Debug, Clone)]
 pub struct Socket {
@@ -17,32 +18,69 @@ pub struct Socket {
 
 impl Socket {
     pub async fn connect(url: &str, safe: bool) -> Result<Socket, Error> {
-        Ok(Socket { conn: Connection::connect(url).await?, safe })
+        Self::connect_with_config(url, safe, &ConnectionConfig::default()).await
+    }
+
+    /// Connects with the given rate limiter, which may be shared with other sockets of the same account.
+    pub async fn connect_with_limiter(url: &str, safe: bool, limiter: RateLimiter) -> Result<Socket, Error> {
+        let config = ConnectionConfig { limiter: Some(limiter), ..Default::default() };
+        Self::connect_with_config(url, safe, &config).await
+    }
+
+    pub async fn connect_with_config(url: &str, safe: bool, config: &ConnectionConfig) -> Result<Socket, Error> {
+        let conn = Connection::connect(url, config, Channel::Socket).await?;
+        conn.spawn_dispatching_task();
+        if let Some(interval) = config.keepalive() {
+            let ping = Command {
+                custom_tag: Some(String::from(KEEPALIVE_TAG)),
+                ..Command::new("ping")
+            };
+            conn.spawn_keepalive_task(interval, serde_json::to_string(&ping)?);
+        }
+        Ok(Socket { conn, safe })
+    }
+
+    pub(crate) fn health(&self) -> watch::Receiver<Health> {
+        self.conn.health()
+    }
+
+    pub fn limiter(&self) -> RateLimiter {
+        self.conn.limiter().clone()
     }
 
     pub async fn skip_delay(&self) {
         self.conn.skip_delay().await;
     }
 
+    /// Logs out and closes the connection. The connection is closed even if the logout fails.
+    pub async fn close(&self) -> Result<(), Error> {
+        if self.conn.is_closed() {
+            return Ok(());
+        }
+
+        let logout = self.logout().await;
+        self.conn.close().await;
+        match logout {
+            Err(err) if !err.is_connection_error() => Err(err),
+            _ => Ok(()),
+        }
+    }
+
     pub async fn login(&self, account_id: &str, password: &str) -> Result<LoginResponse, Error> {
-        self.conn
-            .transaction(&format!(
-                "{{\"command\":\"login\",\"arguments\":{{\"userId\":\"{}\",\"password\":\"{}\"}}}}",
-                account_id, password
-            ))
-            .await
+        let arguments = LoginArgs { user_id: account_id, password };
+        self.conn.transaction(Command::with_arguments("login", arguments)).await
     }
 
     pub async fn logout(&self) -> Result<LogoutResponse, Error> {
-        self.conn.transaction("{\"command\":\"logout\"}").await
+        self.conn.transaction(Command::new("logout")).await
     }
 
     pub async fn get_all_symbols(&self) -> Result<Response<Vec<Symbol>>, Error> {
-        self.conn.transaction("{\"command\":\"getAllSymbols\"}").await
+        self.conn.transaction(Command::new("getAllSymbols")).await
     }
 
     pub async fn get_calendar(&self) -> Result<Response<Vec<Calendar>>, Error> {
-        self.conn.transaction("{\"command\":\"getCalendar\"}").await
+        self.conn.transaction(Command::new("getCalendar")).await
     }
 
     pub async fn get_chart_last_request(
@@ -51,11 +89,9 @@ impl Socket {
         start: i64,
         period: Period,
     ) -> Result<Response<ChartRateInfo>, Error> {
+        let info = ChartLastInfo { period: period.into(), start, symbol };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getChartLastRequest\",\"arguments\":{{\"info\":{{\"period\":{},\"start\":{},\"symbol\":\"{}\"}}}}}}",
-                period as i64, start, symbol
-            ))
+            .transaction(Command::with_arguments("getChartLastRequest", InfoArgs { info }).with_symbol(symbol))
             .await
     }
 
@@ -67,57 +103,45 @@ impl Socket {
         period: Period,
         ticks: i64,
     ) -> Result<Response<ChartRateInfo>, Error> {
+        let info = ChartRangeInfo { end, period: period.into(), start, symbol, ticks };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getChartRangeRequest\",\"arguments\":{{\"info\":{{\"end\":{},\"period\":{},\"start\":{},\"symbol\":\"{}\",\"ticks\":{}}}}}}}",
-                end, period as i64, start, symbol, ticks
-            ))
+            .transaction(Command::with_arguments("getChartRangeRequest", InfoArgs { info }).with_symbol(symbol))
             .await
     }
 
     pub async fn get_commission_def(&self, symbol: &str, volume: f64) -> Result<Response<CommissionDef>, Error> {
+        let arguments = SymbolVolumeArgs { symbol, volume };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getCommissionDef\",\"arguments\":{{\"symbol\":\"{}\",\"volume\":{}}}}}",
-                symbol,
-                trim!(volume)
-            ))
+            .transaction(Command::with_arguments("getCommissionDef", arguments).with_symbol(symbol))
             .await
     }
 
     pub async fn get_current_user_data(&self) -> Result<Response<CurrentUserData>, Error> {
-        self.conn.transaction("{\"command\":\"getCurrentUserData\"}").await
+        self.conn.transaction(Command::new("getCurrentUserData")).await
     }
 
     pub async fn get_ibs_history(&self, start: i64, end: i64) -> Result<Response<Vec<IBData>>, Error> {
+        let arguments = TimeRangeArgs { end, start };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getIbsHistory\",\"arguments\":{{\"end\":{},\"start\":{}}}}}",
-                end, start
-            ))
+            .transaction(Command::with_arguments("getIbsHistory", arguments))
             .await
     }
 
     pub async fn get_margin_level(&self) -> Result<Response<MarginLevel>, Error> {
-        self.conn.transaction("{\"command\":\"getMarginLevel\"}").await
+        self.conn.transaction(Command::new("getMarginLevel")).await
     }
 
     pub async fn get_margin_trade(&self, symbol: &str, volume: f64) -> Result<Response<MarginTrade>, Error> {
+        let arguments = SymbolVolumeArgs { symbol, volume };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getMarginTrade\",\"arguments\":{{\"symbol\":\"{}\",\"volume\":{}}}}}",
-                symbol,
-                trim!(volume)
-            ))
+            .transaction(Command::with_arguments("getMarginTrade", arguments).with_symbol(symbol))
             .await
     }
 
     pub async fn get_news(&self, start: i64, end: i64) -> Result<Response<Vec<News>>, Error> {
+        let arguments = TimeRangeArgs { end, start };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getNews\",\"arguments\":{{\"end\":{},\"start\":{}}}}}",
-                end, start
-            ))
+            .transaction(Command::with_arguments("getNews", arguments))
             .await
     }
 
@@ -129,28 +153,23 @@ impl Socket {
         close_price: f64,
         volume: f64,
     ) -> Result<Response<ProfitCalculation>, Error> {
+        let arguments = ProfitCalculationArgs { close_price, cmd: cmd.into(), open_price, symbol, volume };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getProfitCalculation\",\"arguments\":{{\"closePrice\":{},\"cmd\":{},\"openPrice\":{},\"symbol\":\"{}\",\"volume\":{}}}}}",
-                trim!(close_price), cmd as i64, trim!(open_price), symbol, trim!(volume)
-            ))
+            .transaction(Command::with_arguments("getProfitCalculation", arguments).with_symbol(symbol))
             .await
     }
 
     pub async fn get_server_time(&self) -> Result<Response<ServerTime>, Error> {
-        self.conn.transaction("{\"command\":\"getServerTime\"}").await
+        self.conn.transaction(Command::new("getServerTime")).await
     }
 
     pub async fn get_step_rules(&self) -> Result<Response<Vec<StepRule>>, Error> {
-        self.conn.transaction("{\"command\":\"getStepRules\"}").await
+        self.conn.transaction(Command::new("getStepRules")).await
     }
 
     pub async fn get_symbol(&self, symbol: &str) -> Result<Response<Symbol>, Error> {
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getSymbol\",\"arguments\":{{\"symbol\":\"{}\"}}}}",
-                symbol
-            ))
+            .transaction(Command::with_arguments("getSymbol", SymbolArgs { symbol }).with_symbol(symbol))
             .await
     }
 
@@ -160,87 +179,76 @@ impl Socket {
         timestamp: i64,
         level: i64,
     ) -> Result<Response<TickPrices>, Error> {
+        let arguments = TickPricesArgs { level, symbols: &symbols, timestamp };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getTickPrices\",\"arguments\":{{\"level\":{},\"symbols\":[{}],\"timestamp\":{}}}}}",
-                level,
-                symbols
-                    .iter()
-                    .map(|s| format!("\"{}\"", s))
-                    .collect::<Vec<String>>()
-                    .join(","),
-                timestamp
-            ))
+            .transaction(Command::with_arguments("getTickPrices", arguments))
             .await
     }
 
     pub async fn get_trade_records(&self, orders: Vec<i64>) -> Result<Response<Vec<Trade>>, Error> {
+        let arguments = OrdersArgs { orders: &orders };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getTradeRecords\",\"arguments\":{{\"orders\":[{}]}}}}",
-                orders.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(",")
-            ))
+            .transaction(Command::with_arguments("getTradeRecords", arguments))
             .await
     }
 
     pub async fn get_trades(&self, opened_only: bool) -> Result<Response<Vec<Trade>>, Error> {
+        let arguments = OpenedOnlyArgs { opened_only };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getTrades\",\"arguments\":{{\"openedOnly\":{}}}}}",
-                opened_only
-            ))
+            .transaction(Command::with_arguments("getTrades", arguments))
             .await
     }
 
     pub async fn get_trades_history(&self, start: i64, end: i64) -> Result<Response<Vec<Trade>>, Error> {
+        let arguments = TimeRangeArgs { end, start };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getTradesHistory\",\"arguments\":{{\"end\":{},\"start\":{}}}}}",
-                end, start
-            ))
+            .transaction(Command::with_arguments("getTradesHistory", arguments))
             .await
     }
 
     pub async fn get_trading_hours(&self, symbols: Vec<&str>) -> Result<Response<Vec<TradingHours>>, Error> {
+        let arguments = SymbolsArgs { symbols: &symbols };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"getTradingHours\",\"arguments\":{{\"symbols\":[{}]}}}}",
-                symbols
-                    .iter()
-                    .map(|s| format!("\"{}\"", s))
-                    .collect::<Vec<String>>()
-                    .join(",")
-            ))
+            .transaction(Command::with_arguments("getTradingHours", arguments))
             .await
     }
 
     pub async fn get_version(&self) -> Result<Response<Version>, Error> {
-        self.conn.transaction("{\"command\":\"getVersion\"}").await
+        self.conn.transaction(Command::new("getVersion")).await
     }
 
     pub async fn ping(&self) -> Result<PingResponse, Error> {
-        self.conn.transaction("{\"command\":\"ping\"}").await
+        self.conn.transaction(Command::new("ping")).await
     }
 
     pub async fn trade_transaction(&self, transaction: Transaction) -> Result<Response<Order>, Error> {
-        if self.safe == true {
+        if self.safe {
             return Err(Error::TradingIsDisabled);
         }
 
+        let trade_trans_info = TradeTransInfo {
+            cmd: transaction.cmd.into(),
+            custom_comment: &transaction.custom_comment,
+            expiration: transaction.expiration,
+            offset: transaction.offset,
+            order: transaction.order,
+            price: transaction.price,
+            sl: transaction.sl,
+            symbol: &transaction.symbol,
+            tp: transaction.tp,
+            type_: transaction.type_.into(),
+            volume: transaction.volume,
+        };
+        let arguments = TradeTransactionArgs { trade_trans_info };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"tradeTransaction\",\"arguments\":{{\"tradeTransInfo\":{{\"cmd\":{},\"customComment\":\"{}\",\"expiration\":{},\"offset\":{},\"order\":{},\"price\":{},\"sl\":{},\"symbol\":\"{}\",\"tp\":{},\"type\":{},\"volume\":{}}}}}}}",
-                transaction.cmd as i64, transaction.custom_comment, transaction.expiration, transaction.offset, transaction.order, trim!(transaction.price), trim!(transaction.sl), transaction.symbol, trim!(transaction.tp), transaction.type_ as i64, trim!(transaction.volume)
-            ))
+            .transaction(Command::with_arguments("tradeTransaction", arguments).with_symbol(&transaction.symbol))
             .await
     }
 
     pub async fn trade_transaction_status(&self, order: i64) -> Result<Response<TradeStatus>, Error> {
+        let arguments = OrderArgs { order };
         self.conn
-            .transaction(&format!(
-                "{{\"command\":\"tradeTransactionStatus\",\"arguments\":{{\"order\":{}}}}}",
-                order
-            ))
+            .transaction(Command::with_arguments("tradeTransactionStatus", arguments))
             .await
     }
 }
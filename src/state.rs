@@ -0,0 +1,214 @@
+use crate::stream::Heartbeat;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::watch;
+use tokio::time::{sleep_until, Duration, Instant};
+
+/// State of the link to the broker, see `XApi::state` and `Supervisor::state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Connecting the socket and logging in.
+    Connecting,
+    /// Logged in, connecting the stream.
+    LoggedIn,
+    /// Both connections are up.
+    Streaming,
+    /// A connection failed, or a ping failed, or `KeepAlive` records stopped arriving.
+    Degraded,
+    /// A `Supervisor` is connecting again.
+    Reconnecting,
+    /// A `Supervisor` gave up reconnecting, on an error which is not a connection error, e.g. refused credentials.
+    Failed,
+    /// Closed by the application.
+    Closed,
+}
+
+/// Health of a single connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Health {
+    Healthy,
+    Degraded,
+    Closed,
+}
+
+/// Publisher of the connection state. Clones publish to the same channel, but only the latest session may publish,
+/// so a connection replaced by a `Supervisor` does not report its failure.
+#[derive(Debug, Clone)]
+pub(crate) struct StateReporter {
+    sender: Arc<watch::Sender<ConnectionState>>,
+    session: u64,
+    current: Arc<AtomicU64>,
+}
+
+impl StateReporter {
+    pub fn new() -> StateReporter {
+        StateReporter {
+            sender: Arc::new(watch::channel(ConnectionState::Connecting).0),
+            session: 0,
+            current: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Returns a reporter of a new session. Reporters of the previous sessions go silent.
+    pub fn next_session(&self) -> StateReporter {
+        let session = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+        StateReporter { session, ..self.clone() }
+    }
+
+    pub fn is_current(&self) -> bool {
+        self.current.load(Ordering::SeqCst) == self.session
+    }
+
+    pub fn set(&self, state: ConnectionState) {
+        if self.is_current() {
+            self.sender
+                .send_if_modified(|current| std::mem::replace(current, state) != state);
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<ConnectionState> {
+        self.sender.subscribe()
+    }
+
+    /// Spawns a task, which derives the state from the health of both connections
+    /// and the arrival of `KeepAlive` records, until the state is `Closed` or the session is replaced.
+    pub fn spawn_monitor(
+        &self,
+        mut socket: watch::Receiver<Health>,
+        mut stream: watch::Receiver<Health>,
+        mut keepalive: watch::Receiver<Option<Heartbeat>>,
+        keepalive_timeout: Duration,
+    ) {
+        let reporter = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let last_keepalive = *keepalive.borrow_and_update();
+                let stale_at = last_keepalive.map(|last| last.received + keepalive_timeout);
+                let health = [*socket.borrow_and_update(), *stream.borrow_and_update()];
+                let state = if health.contains(&Health::Closed) {
+                    ConnectionState::Closed
+                } else if health.contains(&Health::Degraded) || stale_at.is_some_and(|at| at <= Instant::now()) {
+                    ConnectionState::Degraded
+                } else {
+                    ConnectionState::Streaming
+                };
+
+                if !reporter.is_current() {
+                    break;
+                }
+                reporter.set(state);
+                if state == ConnectionState::Closed {
+                    break;
+                }
+
+                let stale = async {
+                    match stale_at {
+                        Some(at) if at > Instant::now() => sleep_until(at).await,
+                        _ => std::future::pending().await,
+                    }
+                };
+                let changed = tokio::select! {
+                    changed = socket.changed() => changed,
+                    changed = stream.changed() => changed,
+                    changed = keepalive.changed() => changed,
+                    _ = stale => Ok(()),
+                };
+                if changed.is_err() {
+                    break; // connections dropped
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::KeepAlive;
+    use crate::mock::{MockServer, Reply};
+    use crate::supervisor::{Event, Supervisor};
+    use crate::XApiBuilder;
+
+    use tokio::time::timeout;
+
+    async fn wait_for(state: &mut watch::Receiver<ConnectionState>, expected: ConnectionState) {
+        let wait = state.wait_for(|state| *state == expected);
+        timeout(Duration::from_secs(5), wait).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_state_follows_keepalives_and_close() {
+        let server = MockServer::start().await.unwrap();
+        let x = XApiBuilder::new(&server.credentials())
+            .heartbeat_timeout(Duration::from_millis(200))
+            .connect()
+            .await
+            .unwrap();
+        let mut state = x.state();
+        wait_for(&mut state, ConnectionState::Streaming).await;
+
+        x.stream.get_keep_alive().await.unwrap();
+        server.wait_for("getKeepAlive", Duration::from_secs(5)).await.unwrap();
+        server.push("keepAlive", KeepAlive { timestamp: 1 });
+        wait_for(&mut state, ConnectionState::Degraded).await;
+
+        server.push("keepAlive", KeepAlive { timestamp: 2 });
+        wait_for(&mut state, ConnectionState::Streaming).await;
+
+        x.close().await.unwrap();
+        wait_for(&mut state, ConnectionState::Closed).await;
+    }
+
+    #[tokio::test]
+    async fn test_state_degrades_on_connection_failure() {
+        let server = MockServer::start().await.unwrap();
+        let x = crate::connect(&server.credentials()).await.unwrap();
+        let mut state = x.state();
+        wait_for(&mut state, ConnectionState::Streaming).await;
+
+        server.drop_connections();
+        wait_for(&mut state, ConnectionState::Degraded).await;
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_state_across_reconnection() {
+        let server = MockServer::start().await.unwrap();
+        let supervisor = Supervisor::connect(&server.credentials())
+            .await
+            .unwrap()
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(100));
+        let mut state = supervisor.state();
+        wait_for(&mut state, ConnectionState::Streaming).await;
+
+        server.drop_connections();
+        let listen = tokio::spawn({
+            let supervisor = supervisor.clone();
+            async move { supervisor.listen().await }
+        });
+        wait_for(&mut state, ConnectionState::Reconnecting).await;
+        assert!(matches!(listen.await.unwrap(), Ok(Event::Reconnected)));
+        wait_for(&mut state, ConnectionState::Streaming).await;
+    }
+
+    #[tokio::test]
+    async fn test_supervisor_state_when_reconnection_fails() {
+        let server = MockServer::start().await.unwrap();
+        let supervisor = Supervisor::connect(&server.credentials())
+            .await
+            .unwrap()
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(100));
+        let mut state = supervisor.state();
+        wait_for(&mut state, ConnectionState::Streaming).await;
+
+        server.on(
+            "login",
+            Reply::error("BE005", "userPasswordCheck: Invalid login or password"),
+        );
+        server.drop_connections();
+        let err = supervisor.listen().await.unwrap_err();
+        assert!(err.is_auth_error());
+        assert_eq!(*state.borrow(), ConnectionState::Failed);
+    }
+}
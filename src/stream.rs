@@ -1,19 +1,594 @@
+use crate::cassette::Channel;
+use crate::command::*;
+use crate::config::ConnectionConfig;
 use crate::connection::Connection;
 use crate::data::*;
 use crate::error::Error;
+use crate::handle::*;
+use crate::limiter::RateLimiter;
+use crate::state::Health;
 
+use log::{debug, warn};
 use serde::de::DeserializeOwned;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::{broadcast, watch};
+use tokio::time::{timeout, Duration, Instant};
+
+/// Stream subscription, as issued by one of the `Stream::get_*` methods.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Subscription {
+    Balance,
+    Candles {
+        symbol: String,
+    },
+    KeepAlive,
+    News,
+    Profits,
+    TickPrices {
+        symbol: String,
+        min_arrival_time: i64,
+        max_level: i64,
+    },
+    Trades,
+    TradeStatus,
+}
+
+impl Subscription {
+    /// Whether both subscriptions are stopped by the same `stop*` command.
+    fn same_channel(&self, other: &Subscription) -> bool {
+        self.channel() == other.channel()
+    }
+
+    /// Returns the subscription without its parameters, identifying the server side subscription.
+    pub(crate) fn channel(&self) -> Subscription {
+        match self {
+            Subscription::TickPrices { symbol, .. } => {
+                Subscription::TickPrices { symbol: symbol.clone(), min_arrival_time: 0, max_level: 0 }
+            }
+            other => other.clone(),
+        }
+    }
+
+    /// Returns the symbol the subscription is limited to, if any.
+    pub fn symbol(&self) -> Option<&str> {
+        match self {
+            Subscription::Candles { symbol } | Subscription::TickPrices { symbol, .. } => Some(symbol),
+            _ => None,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+enum Published {
+    Record(Record),
+    /// Raw message, which could not be decoded. Every receiver decodes it again, to get its own error.
+    Invalid(String),
+    /// Reading has stopped, see `Records::failure`.
+    Closed,
+}
+
+/// Handles of one subscription channel, see `Stream::acquire`.
+#[derive(Debug, Default)]
+struct Holders {
+    count: usize,
+    /// Odd while a start is in flight, so that handles acquired meanwhile share its outcome.
+    attempt: u64,
+    gate: Arc<tokio::sync::Mutex<Gate>>,
+}
+
+/// Reference taken by `Stream::acquire`, released on drop unless disarmed.
+struct Acquiring<'a> {
+    stream: &'a Stream,
+    subscription: &'a Subscription,
+    armed: bool,
+}
+
+impl Drop for Acquiring<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.stream.release_and_stop(self.subscription);
+        }
+    }
+}
+
+/// Server side state of a subscription channel. Starts and stops of the channel run under its lock.
+#[derive(Debug, Default)]
+struct Gate {
+    started: bool,
+    failure: Option<(u64, Error)>,
+}
+
+#[derive(Debug)]
+struct Records {
+    sender: broadcast::Sender<Published>,
+    failure: Mutex<Option<Error>>,
+}
+
+/// Last `KeepAlive` record, see `Stream::heartbeat`.
+#[derive(Debug, Clone, Copy)]
+pub struct Heartbeat {
+    /// Server time of the record, in milliseconds since the Unix epoch.
+    pub timestamp: i64,
+    /// Local arrival time.
+    pub received: Instant,
+    /// Estimated one-way delay in milliseconds: the local clock at arrival minus the server timestamp.
+    /// Includes the offset between both clocks, so it may be negative.
+    pub delay: i64,
+}
+
+impl Heartbeat {
+    fn new(timestamp: i64) -> Heartbeat {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as i64;
+        Heartbeat { timestamp, received: Instant::now(), delay: now - timestamp }
+    }
+}
+
+/// Independent receiver of stream records, created by `Stream::subscribe`.
+#[derive(Debug)]
+pub struct RecordReceiver {
+    receiver: broadcast::Receiver<Published>,
+    records: Arc<Records>,
+}
+
+impl RecordReceiver {
+    /// Waits for the next record. Returns `Error::Lagged` if the receiver fell behind
+    /// and the oldest records were dropped; the following call continues with the oldest retained record.
+    pub async fn recv(&mut self) -> Result<Record, Error> {
+        let published = match self.receiver.try_recv() {
+            Ok(published) => published,
+            Err(TryRecvError::Empty) => match self.failure() {
+                Some(err) => return Err(err),
+                None => match self.receiver.recv().await {
+                    Ok(published) => published,
+                    Err(RecvError::Lagged(skipped)) => return Err(Error::Lagged { skipped }),
+                    Err(RecvError::Closed) => return Err(Error::ConnectionClosed),
+                },
+            },
+            Err(TryRecvError::Lagged(skipped)) => return Err(Error::Lagged { skipped }),
+            Err(TryRecvError::Closed) => return Err(Error::ConnectionClosed),
+        };
+
+        match published {
+            Published::Record(record) => Ok(record),
+            Published::Invalid(message) => parse_record(&message),
+            Published::Closed => Err(self.failure().unwrap_or(Error::ConnectionClosed)),
+        }
+    }
+
+    fn failure(&self) -> Option<Error> {
+        self.records.failure.lock().unwrap().as_ref().map(Error::replicate)
+    }
+}
+
+#[derive(Clone)]
 pub struct Stream {
     conn: Connection,
     stream_session_id: String,
+    subscriptions: Arc<Mutex<Vec<Subscription>>>,
+    handles: Arc<Mutex<HashMap<Subscription, Holders>>>,
+    records: Arc<Records>,
+    listener: Arc<tokio::sync::Mutex<RecordReceiver>>,
+    keepalive: Arc<watch::Sender<Option<Heartbeat>>>,
+}
+
+impl std::fmt::Debug for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Stream")
+            .field("conn", &self.conn)
+            .field("stream_session_id", &"***")
+            .field("subscriptions", &self.subscriptions())
+            .finish_non_exhaustive()
+    }
 }
 
 impl Stream {
     pub async fn connect(url: &str, stream_session_id: String) -> Result<Stream, Error> {
-        Ok(Stream { conn: Connection::connect(url).await?, stream_session_id })
+        Self::connect_with_config(url, stream_session_id, &ConnectionConfig::default()).await
+    }
+
+    /// Connects with the given rate limiter, which may be shared with other streams of the same account.
+    pub async fn connect_with_limiter(
+        url: &str,
+        stream_session_id: String,
+        limiter: RateLimiter,
+    ) -> Result<Stream, Error> {
+        let config = ConnectionConfig { limiter: Some(limiter), ..Default::default() };
+        Self::connect_with_config(url, stream_session_id, &config).await
+    }
+
+    pub async fn connect_with_config(
+        url: &str,
+        stream_session_id: String,
+        config: &ConnectionConfig,
+    ) -> Result<Stream, Error> {
+        let conn = Connection::connect(url, config, Channel::Stream).await?;
+        if let Some(interval) = config.keepalive() {
+            let ping = StreamCommand {
+                command: "ping",
+                stream_session_id: Some(&stream_session_id),
+                arguments: (),
+            };
+            conn.spawn_keepalive_task(interval, serde_json::to_string(&ping)?);
+        }
+        let records = Arc::new(Records {
+            sender: broadcast::channel(config.stream_capacity.max(1)).0,
+            failure: Mutex::new(None),
+        });
+        let listener = RecordReceiver {
+            receiver: records.sender.subscribe(),
+            records: records.clone(),
+        };
+
+        let metrics = config.metrics.clone();
+        let keepalive = Arc::new(watch::channel(None).0);
+        let heartbeat = Arc::downgrade(&keepalive);
+        let publisher = Arc::downgrade(&records);
+        conn.spawn_reading_task(move |message| {
+            let Some(records) = publisher.upgrade() else {
+                return ControlFlow::Break(());
+            };
+            match message {
+                Ok(message) => {
+                    let published = match parse_record(&message) {
+                        Ok(record) => {
+                            if let Some(metrics) = &metrics {
+                                metrics.record(&record);
+                            }
+                            if let (Record::KeepAlive(keep_alive), Some(heartbeat)) = (&record, heartbeat.upgrade()) {
+                                heartbeat.send_replace(Some(Heartbeat::new(keep_alive.timestamp)));
+                            }
+                            Published::Record(record)
+                        }
+                        Err(_) => Published::Invalid(message),
+                    };
+                    records.sender.send(published).ok();
+                    ControlFlow::Continue(())
+                }
+                Err(err) => {
+                    debug!("Reading stopped: {}", err);
+                    records.failure.lock().unwrap().get_or_insert(err);
+                    records.sender.send(Published::Closed).ok();
+                    ControlFlow::Break(())
+                }
+            }
+        });
+
+        let stream = Stream {
+            conn,
+            stream_session_id,
+            subscriptions: Arc::new(Mutex::new(Vec::new())),
+            handles: Arc::new(Mutex::new(HashMap::new())),
+            records,
+            listener: Arc::new(tokio::sync::Mutex::new(listener)),
+            keepalive,
+        };
+        if config.heartbeat_watchdog {
+            stream.get_keep_alive().await?;
+            stream.spawn_watchdog(config.heartbeat_timeout);
+        }
+        Ok(stream)
+    }
+
+    /// Spawns a task, which fails the stream with `Error::StaleConnection` and stops the connection,
+    /// when no `KeepAlive` record arrives for the given time.
+    fn spawn_watchdog(&self, heartbeat_timeout: Duration) {
+        let mut heartbeats = self.keepalive.subscribe();
+        let records = Arc::downgrade(&self.records);
+        let tripwire = self.conn.tripwire();
+        tokio::spawn(async move {
+            // The timer starts with the first heartbeat, as documented for `heartbeat_timeout`.
+            if heartbeats.wait_for(|heartbeat| heartbeat.is_some()).await.is_err() {
+                return; // stream dropped
+            }
+            loop {
+                match timeout(heartbeat_timeout, heartbeats.changed()).await {
+                    Ok(Ok(())) => continue,
+                    Ok(Err(_)) => break, // stream dropped
+                    Err(_) => {}
+                }
+                let Some(records) = records.upgrade() else {
+                    break;
+                };
+                if tripwire.is_closed() {
+                    break;
+                }
+                warn!("No KeepAlive received for {:?}", heartbeat_timeout);
+                records.failure.lock().unwrap().get_or_insert(Error::StaleConnection);
+                tripwire.trip();
+                break;
+            }
+        });
+    }
+
+    /// Returns a new receiver of stream records. Every receiver gets every record received after its creation.
+    pub fn subscribe(&self) -> RecordReceiver {
+        RecordReceiver {
+            receiver: self.records.sender.subscribe(),
+            records: self.records.clone(),
+        }
+    }
+
+    pub fn limiter(&self) -> RateLimiter {
+        self.conn.limiter().clone()
+    }
+
+    /// Returns the currently active subscriptions.
+    pub fn subscriptions(&self) -> Vec<Subscription> {
+        self.subscriptions.lock().unwrap().clone()
+    }
+
+    pub async fn subscribe_balance(&self) -> Result<BalanceSubscription, Error> {
+        self.acquire(Subscription::Balance).await
+    }
+
+    pub async fn subscribe_candles(&self, symbol: &str) -> Result<CandleSubscription, Error> {
+        self.acquire(Subscription::Candles { symbol: symbol.to_owned() }).await
+    }
+
+    pub async fn subscribe_keep_alive(&self) -> Result<KeepAliveSubscription, Error> {
+        self.acquire(Subscription::KeepAlive).await
+    }
+
+    pub async fn subscribe_news(&self) -> Result<NewsSubscription, Error> {
+        self.acquire(Subscription::News).await
+    }
+
+    pub async fn subscribe_profits(&self) -> Result<ProfitSubscription, Error> {
+        self.acquire(Subscription::Profits).await
+    }
+
+    /// Subscribes to tick prices. If the symbol is already subscribed by another handle,
+    /// the existing server subscription is shared, with its original parameters.
+    pub async fn subscribe_tick_prices(
+        &self,
+        symbol: &str,
+        min_arrival_time: i64,
+        max_level: i64,
+    ) -> Result<TickSubscription, Error> {
+        self.acquire(Subscription::TickPrices { symbol: symbol.to_owned(), min_arrival_time, max_level })
+            .await
+    }
+
+    pub async fn subscribe_trades(&self) -> Result<TradeSubscription, Error> {
+        self.acquire(Subscription::Trades).await
+    }
+
+    pub async fn subscribe_trade_status(&self) -> Result<TradeStatusSubscription, Error> {
+        self.acquire(Subscription::TradeStatus).await
+    }
+
+    /// Returns a `futures::Stream` of tick prices of the symbol.
+    pub async fn ticks(&self, symbol: &str) -> Result<RecordStream<Tick>, Error> {
+        Ok(self.subscribe_tick_prices(symbol, 0, 0).await?.into_stream())
+    }
+
+    /// Returns a `futures::Stream` of candles of the symbol.
+    pub async fn candles(&self, symbol: &str) -> Result<RecordStream<Candle>, Error> {
+        Ok(self.subscribe_candles(symbol).await?.into_stream())
+    }
+
+    /// Returns a `futures::Stream` of balance updates.
+    pub async fn balances(&self) -> Result<RecordStream<Balance>, Error> {
+        Ok(self.subscribe_balance().await?.into_stream())
+    }
+
+    /// Returns a `futures::Stream` of trade updates.
+    pub async fn trades(&self) -> Result<RecordStream<Trade>, Error> {
+        Ok(self.subscribe_trades().await?.into_stream())
+    }
+
+    /// Returns a handle to the subscription, starting it on the server, unless another handle already did.
+    /// While a start is in flight, further handles wait for it and share its outcome.
+    async fn acquire<T: StreamRecord>(&self, subscription: Subscription) -> Result<SubscriptionHandle<T>, Error> {
+        let receiver = self.subscribe();
+        let channel = subscription.channel();
+        let (ticket, gate) = {
+            let mut handles = self.handles.lock().unwrap();
+            let holders = handles.entry(channel.clone()).or_default();
+            holders.count += 1;
+            (holders.attempt, holders.gate.clone())
+        };
+        // Releases the reference, if this future fails or is dropped before the handle takes it over.
+        let mut guard = Acquiring { stream: self, subscription: &subscription, armed: true };
+
+        let mut gate = gate.lock().await;
+        if !gate.started {
+            let result = match &gate.failure {
+                Some((attempt, err)) if *attempt == ticket => Err(err.replicate()),
+                _ => {
+                    let attempt = self.next_attempt(&channel);
+                    // Considered started while in flight, so that a cancelled start is still stopped.
+                    gate.started = true;
+                    let result = self.add_subscription(&subscription).await;
+                    self.next_attempt(&channel);
+                    if let Err(err) = &result {
+                        gate.started = false;
+                        gate.failure = Some((attempt, err.replicate()));
+                    }
+                    result
+                }
+            };
+            result?;
+        }
+        drop(gate);
+        guard.armed = false;
+        drop(guard);
+        Ok(SubscriptionHandle::new(self.clone(), subscription, receiver))
+    }
+
+    /// Advances the start attempt of the channel, see `Holders::attempt`.
+    fn next_attempt(&self, channel: &Subscription) -> u64 {
+        let mut handles = self.handles.lock().unwrap();
+        let holders = handles.entry(channel.clone()).or_default();
+        holders.attempt += 1;
+        holders.attempt
+    }
+
+    /// Drops one handle reference and, if it was the last one, stops the subscription in the background.
+    pub(crate) fn release_and_stop(&self, subscription: &Subscription) {
+        if !self.release(subscription) {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let stream = self.clone();
+        let subscription = subscription.clone();
+        runtime.spawn(async move { stream.stop_released(&subscription).await });
+    }
+
+    /// Drops one handle reference. Returns true, if it was the last one.
+    fn release(&self, subscription: &Subscription) -> bool {
+        let mut handles = self.handles.lock().unwrap();
+        match handles.get_mut(&subscription.channel()) {
+            Some(holders) if holders.count > 0 => {
+                holders.count -= 1;
+                holders.count == 0
+            }
+            _ => false,
+        }
+    }
+
+    /// Stops the subscription on the server, unless it was acquired again, or the stream is closed.
+    /// Runs under the lock of the channel, so it cannot overtake a start of a new handle.
+    pub(crate) async fn stop_released(&self, subscription: &Subscription) {
+        let Some(gate) = self
+            .handles
+            .lock()
+            .unwrap()
+            .get(&subscription.channel())
+            .map(|holders| holders.gate.clone())
+        else {
+            return;
+        };
+        let mut gate = gate.lock().await;
+        if !gate.started || self.is_held(subscription) || self.is_closed() {
+            return;
+        }
+        if self.remove_subscription(subscription).await.is_ok() {
+            gate.started = false;
+        }
+    }
+
+    /// Whether any handle references the subscription.
+    fn is_held(&self, subscription: &Subscription) -> bool {
+        let handles = self.handles.lock().unwrap();
+        handles
+            .get(&subscription.channel())
+            .is_some_and(|holders| holders.count > 0)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.conn.is_closed()
+    }
+
+    pub(crate) fn health(&self) -> watch::Receiver<Health> {
+        self.conn.health()
+    }
+
+    /// Returns the last `KeepAlive` record received.
+    pub fn heartbeat(&self) -> Option<Heartbeat> {
+        *self.keepalive.borrow()
+    }
+
+    pub(crate) fn heartbeats(&self) -> watch::Receiver<Option<Heartbeat>> {
+        self.keepalive.subscribe()
+    }
+
+    /// Stops all active subscriptions and closes the connection.
+    pub async fn close(&self) -> Result<(), Error> {
+        if self.conn.is_closed() {
+            return Ok(());
+        }
+
+        let mut result = Ok(());
+        for subscription in self.subscriptions() {
+            if let Err(err) = self.remove_subscription(&subscription).await {
+                debug!("Failed to stop {:?}: {}", subscription, err);
+                if result.is_ok() && !err.is_connection_error() {
+                    result = Err(err);
+                }
+            }
+        }
+        self.conn.close().await;
+        result
+    }
+
+    /// Issues the `get*` command matching the given subscription.
+    pub async fn add_subscription(&self, subscription: &Subscription) -> Result<(), Error> {
+        match subscription {
+            Subscription::Balance => self.get_balance().await,
+            Subscription::Candles { symbol } => self.get_candles(symbol).await,
+            Subscription::KeepAlive => self.get_keep_alive().await,
+            Subscription::News => self.get_news().await,
+            Subscription::Profits => self.get_profits().await,
+            Subscription::TickPrices { symbol, min_arrival_time, max_level } => {
+                self.get_tick_prices(symbol, *min_arrival_time, *max_level).await
+            }
+            Subscription::Trades => self.get_trades().await,
+            Subscription::TradeStatus => self.get_trade_status().await,
+        }
+    }
+
+    /// Issues the `stop*` command matching the given subscription.
+    pub async fn remove_subscription(&self, subscription: &Subscription) -> Result<(), Error> {
+        match subscription {
+            Subscription::Balance => self.stop_balance().await,
+            Subscription::Candles { symbol } => self.stop_candles(symbol).await,
+            Subscription::KeepAlive => self.stop_keep_alive().await,
+            Subscription::News => self.stop_news().await,
+            Subscription::Profits => self.stop_profits().await,
+            Subscription::TickPrices { symbol, .. } => self.stop_tick_prices(symbol).await,
+            Subscription::Trades => self.stop_trades().await,
+            Subscription::TradeStatus => self.stop_trade_status().await,
+        }
+    }
+
+    async fn send<A: Serialize>(&self, command: &str, arguments: A) -> Result<(), Error> {
+        let command = StreamCommand { command, stream_session_id: None, arguments };
+        let result = self.request(&command).await;
+        self.report(command.command, result)
+    }
+
+    async fn send_with_session<A: Serialize>(&self, command: &str, arguments: A) -> Result<(), Error> {
+        let command = StreamCommand {
+            command,
+            stream_session_id: Some(&self.stream_session_id),
+            arguments,
+        };
+        let result = self.request(&command).await;
+        self.report(command.command, result)
+    }
+
+    async fn request<A: Serialize>(&self, command: &StreamCommand<'_, A>) -> Result<(), Error> {
+        self.conn.request(&serde_json::to_string(command)?).await
+    }
+
+    fn report(&self, command: &str, result: Result<(), Error>) -> Result<(), Error> {
+        if let (Some(metrics), Err(err)) = (self.conn.metrics(), &result) {
+            metrics.request_error(command, err);
+        }
+        result
+    }
+
+    fn track(&self, subscription: Subscription) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions.retain(|s| !s.same_channel(&subscription));
+        subscriptions.push(subscription);
+    }
+
+    fn untrack(&self, subscription: Subscription) {
+        let mut subscriptions = self.subscriptions.lock().unwrap();
+        subscriptions.retain(|s| !s.same_channel(&subscription));
     }
 
     pub async fn skip_delay(&self) {
@@ -21,150 +596,385 @@ impl Stream {
     }
 
     pub async fn get_balance(&self) -> Result<(), Error> {
-        self.conn
-            .request(&format!(
-                "{{\"command\":\"getBalance\",\"streamSessionId\":\"{}\"}}",
-                self.stream_session_id
-            ))
-            .await
+        self.send_with_session("getBalance", ()).await?;
+        self.track(Subscription::Balance);
+        Ok(())
     }
 
     pub async fn stop_balance(&self) -> Result<(), Error> {
-        self.conn.request("{\"command\":\"stopBalance\"}").await
+        self.send("stopBalance", ()).await?;
+        self.untrack(Subscription::Balance);
+        Ok(())
     }
 
     pub async fn get_candles(&self, symbol: &str) -> Result<(), Error> {
-        self.conn
-            .request(&format!(
-                "{{\"command\":\"getCandles\",\"streamSessionId\":\"{}\",\"symbol\":\"{}\"}}",
-                self.stream_session_id, symbol
-            ))
-            .await
+        self.send_with_session("getCandles", SymbolArgs { symbol }).await?;
+        self.track(Subscription::Candles { symbol: symbol.to_owned() });
+        Ok(())
     }
 
     pub async fn stop_candles(&self, symbol: &str) -> Result<(), Error> {
-        self.conn
-            .request(&format!("{{\"command\":\"stopCandles\",\"symbol\":\"{}\"}}", symbol))
-            .await
+        self.send("stopCandles", SymbolArgs { symbol }).await?;
+        self.untrack(Subscription::Candles { symbol: symbol.to_owned() });
+        Ok(())
     }
 
     pub async fn get_keep_alive(&self) -> Result<(), Error> {
-        self.conn
-            .request(&format!(
-                "{{\"command\":\"getKeepAlive\",\"streamSessionId\":\"{}\"}}",
-                self.stream_session_id
-            ))
-            .await
+        self.send_with_session("getKeepAlive", ()).await?;
+        self.track(Subscription::KeepAlive);
+        Ok(())
     }
 
     pub async fn stop_keep_alive(&self) -> Result<(), Error> {
-        self.conn.request("{\"command\":\"stopKeepAlive\"}").await
+        self.send("stopKeepAlive", ()).await?;
+        self.untrack(Subscription::KeepAlive);
+        Ok(())
     }
 
     pub async fn get_news(&self) -> Result<(), Error> {
-        self.conn
-            .request(&format!(
-                "{{\"command\":\"getNews\",\"streamSessionId\":\"{}\"}}",
-                self.stream_session_id
-            ))
-            .await
+        self.send_with_session("getNews", ()).await?;
+        self.track(Subscription::News);
+        Ok(())
     }
 
     pub async fn stop_news(&self) -> Result<(), Error> {
-        self.conn.request("{\"command\":\"stopNews\"}").await
+        self.send("stopNews", ()).await?;
+        self.untrack(Subscription::News);
+        Ok(())
     }
 
     pub async fn get_profits(&self) -> Result<(), Error> {
-        self.conn
-            .request(&format!(
-                "{{\"command\":\"getProfits\",\"streamSessionId\":\"{}\"}}",
-                self.stream_session_id
-            ))
-            .await
+        self.send_with_session("getProfits", ()).await?;
+        self.track(Subscription::Profits);
+        Ok(())
     }
 
     pub async fn stop_profits(&self) -> Result<(), Error> {
-        self.conn.request("{\"command\":\"stopProfits\"}").await
+        self.send("stopProfits", ()).await?;
+        self.untrack(Subscription::Profits);
+        Ok(())
     }
 
     pub async fn get_tick_prices(&self, symbol: &str, min_arrival_time: i64, max_level: i64) -> Result<(), Error> {
-        self.conn
-            .request(&format!(
-                "{{\"command\":\"getTickPrices\",\"streamSessionId\":\"{}\",\"symbol\":\"{}\",\"minArrivalTime\":{},\"maxLevel\":{}}}",
-                self.stream_session_id, symbol, min_arrival_time, max_level
-            ))
-            .await
+        self.send_with_session(
+            "getTickPrices",
+            TickPricesStreamArgs { symbol, min_arrival_time, max_level },
+        )
+        .await?;
+        self.track(Subscription::TickPrices { symbol: symbol.to_owned(), min_arrival_time, max_level });
+        Ok(())
     }
 
     pub async fn stop_tick_prices(&self, symbol: &str) -> Result<(), Error> {
-        self.conn
-            .request(&format!("{{\"command\":\"stopTickPrices\",\"symbol\":\"{}\"}}", symbol))
-            .await
+        self.send("stopTickPrices", SymbolArgs { symbol }).await?;
+        self.untrack(Subscription::TickPrices { symbol: symbol.to_owned(), min_arrival_time: 0, max_level: 0 });
+        Ok(())
     }
 
     pub async fn get_trades(&self) -> Result<(), Error> {
-        self.conn
-            .request(&format!(
-                "{{\"command\":\"getTrades\",\"streamSessionId\":\"{}\"}}",
-                self.stream_session_id
-            ))
-            .await
+        self.send_with_session("getTrades", ()).await?;
+        self.track(Subscription::Trades);
+        Ok(())
     }
 
     pub async fn stop_trades(&self) -> Result<(), Error> {
-        self.conn.request("{\"command\":\"stopTrades\"}").await
+        self.send("stopTrades", ()).await?;
+        self.untrack(Subscription::Trades);
+        Ok(())
     }
 
     pub async fn get_trade_status(&self) -> Result<(), Error> {
-        self.conn
-            .request(&format!(
-                "{{\"command\":\"getTradeStatus\",\"streamSessionId\":\"{}\"}}",
-                self.stream_session_id
-            ))
-            .await
+        self.send_with_session("getTradeStatus", ()).await?;
+        self.track(Subscription::TradeStatus);
+        Ok(())
     }
 
     pub async fn stop_trade_status(&self) -> Result<(), Error> {
-        self.conn.request("{\"command\":\"stopTradeStatus\"}").await
+        self.send("stopTradeStatus", ()).await?;
+        self.untrack(Subscription::TradeStatus);
+        Ok(())
     }
 
     pub async fn ping(&self) -> Result<(), Error> {
-        self.conn
-            .request(&format!(
-                "{{\"command\":\"ping\",\"streamSessionId\":\"{}\"}}",
-                self.stream_session_id
-            ))
-            .await
+        self.send_with_session("ping", ()).await
     }
 
+    /// Waits for the next record. All clones of the stream share a single receiver,
+    /// use `subscribe` for independent receivers.
     pub async fn listen(&self) -> Result<Record, Error> {
-        let record = self.conn.receive().await?;
-
-        #[derive(Deserialize)]
-        struct Command {
-            command: String,
+        let result = self.listener.lock().await.recv().await;
+        #[cfg(feature = "tracing")]
+        match &result {
+            Ok(record) => tracing::debug!(record = record.command(), "Stream record"),
+            Err(err) => tracing::debug!(error = err.kind(), "Stream error"),
         }
-        #[derive(Deserialize)]
-        struct Data<T> {
-            data: T,
+        result
+    }
+}
+
+fn parse_record(record: &str) -> Result<Record, Error> {
+    #[derive(Deserialize)]
+    struct Command {
+        command: String,
+    }
+    #[derive(Deserialize)]
+    struct Data<T> {
+        data: T,
+    }
+
+    fn from<T: DeserializeOwned>(record: &str) -> Result<T, Error> {
+        let d = serde_json::from_str::<Data<T>>(record)?;
+        Ok(d.data)
+    }
+
+    let c = serde_json::from_str::<Command>(record)?;
+    match c.command.as_str() {
+        "balance" => Ok(Record::Balance(from::<Balance>(record)?)),
+        "candle" => Ok(Record::Candle(from::<Candle>(record)?)),
+        "keepAlive" => Ok(Record::KeepAlive(from::<KeepAlive>(record)?)),
+        "news" => Ok(Record::News(from::<News>(record)?)),
+        "profit" => Ok(Record::Profit(from::<Profit>(record)?)),
+        "tickPrices" => Ok(Record::Tick(from::<Tick>(record)?)),
+        "trade" => Ok(Record::Trade(from::<Trade>(record)?)),
+        "tradeStatus" => Ok(Record::TradeStatus(from::<TradeStatus>(record)?)),
+        _ => Err(Error::UnknownRecord { record: record.to_owned() }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+
+    use serde_json::json;
+    use tokio::time::{sleep, Duration};
+
+    async fn connect(server: &MockServer, config: &ConnectionConfig) -> Stream {
+        let url = format!("{}/demoStream", server.url());
+        Stream::connect_with_config(&url, String::from("mock-stream-session-id"), config)
+            .await
+            .unwrap()
+    }
+
+    fn timestamp(record: Record) -> i64 {
+        match record {
+            Record::KeepAlive(keep_alive) => keep_alive.timestamp,
+            other => panic!("unexpected record: {:?}", other),
         }
+    }
 
-        fn from<T: DeserializeOwned>(record: &str) -> Result<T, Error> {
-            let d = serde_json::from_str::<Data<T>>(record)?;
-            Ok(d.data)
+    #[tokio::test]
+    async fn test_every_receiver_gets_every_record() {
+        let server = MockServer::start().await.unwrap();
+        let stream = connect(&server, &ConnectionConfig::default()).await;
+        let mut first = stream.subscribe();
+        let mut second = stream.subscribe();
+
+        server.push("keepAlive", json!({ "timestamp": 1 }));
+        server.push("keepAlive", json!({ "timestamp": 2 }));
+
+        for receiver in [&mut first, &mut second] {
+            assert_eq!(timestamp(receiver.recv().await.unwrap()), 1);
+            assert_eq!(timestamp(receiver.recv().await.unwrap()), 2);
         }
+        assert_eq!(timestamp(stream.listen().await.unwrap()), 1);
+    }
 
-        let c = serde_json::from_str::<Command>(&record)?;
-        match c.command.as_str() {
-            "balance" => Ok(Record::Balance(from::<Balance>(&record)?)),
-            "candle" => Ok(Record::Candle(from::<Candle>(&record)?)),
-            "keepAlive" => Ok(Record::KeepAlive(from::<KeepAlive>(&record)?)),
-            "news" => Ok(Record::News(from::<News>(&record)?)),
-            "profit" => Ok(Record::Profit(from::<Profit>(&record)?)),
-            "tickPrices" => Ok(Record::Tick(from::<Tick>(&record)?)),
-            "trade" => Ok(Record::Trade(from::<Trade>(&record)?)),
-            "tradeStatus" => Ok(Record::TradeStatus(from::<TradeStatus>(&record)?)),
-            _ => Err(Error::UnknownRecord { record }),
+    #[tokio::test]
+    async fn test_lagged_receiver() {
+        let server = MockServer::start().await.unwrap();
+        let config = ConnectionConfig { stream_capacity: 2, ..Default::default() };
+        let stream = connect(&server, &config).await;
+        let mut receiver = stream.subscribe();
+
+        for timestamp in 1..=5 {
+            server.push("keepAlive", json!({ "timestamp": timestamp }));
         }
+        sleep(Duration::from_millis(300)).await;
+
+        assert!(matches!(receiver.recv().await, Err(Error::Lagged { skipped: 3 })));
+        assert_eq!(timestamp(receiver.recv().await.unwrap()), 4);
+        assert_eq!(timestamp(receiver.recv().await.unwrap()), 5);
+    }
+
+    fn count(server: &MockServer, command: &str) -> usize {
+        server.received().iter().filter(|r| r.command == command).count()
+    }
+
+    #[tokio::test]
+    async fn test_handles_receive_own_records() {
+        let server = MockServer::start().await.unwrap();
+        let stream = connect(&server, &ConnectionConfig::default()).await;
+        let mut eurusd = stream.subscribe_tick_prices("EURUSD", 0, 0).await.unwrap();
+        let mut candles = stream.subscribe_candles("EURUSD").await.unwrap();
+
+        let tick = |symbol: &str, ask| Tick { symbol: String::from(symbol), ask, ..Default::default() };
+        server.push("tickPrices", tick("GBPUSD", 1.0));
+        server.push(
+            "candle",
+            Candle {
+                symbol: String::from("EURUSD"),
+                close: 3.0,
+                ..Default::default()
+            },
+        );
+        server.push("tickPrices", tick("EURUSD", 2.0));
+
+        assert_eq!(eurusd.recv().await.unwrap().ask, 2.0);
+        assert_eq!(candles.recv().await.unwrap().close, 3.0);
+    }
+
+    #[tokio::test]
+    async fn test_handles_share_subscription() {
+        let server = MockServer::start().await.unwrap();
+        let stream = connect(&server, &ConnectionConfig::default()).await;
+        let first = stream.subscribe_tick_prices("EURUSD", 0, 0).await.unwrap();
+        let second = stream.subscribe_tick_prices("EURUSD", 100, 1).await.unwrap();
+        server.wait_for("getTickPrices", Duration::from_secs(5)).await.unwrap();
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(count(&server, "getTickPrices"), 1);
+
+        drop(first);
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(count(&server, "stopTickPrices"), 0);
+
+        drop(second);
+        let stop = server.wait_for("stopTickPrices", Duration::from_secs(5)).await.unwrap();
+        assert_eq!(stop.json["symbol"], "EURUSD");
+        assert!(stream.subscriptions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handles_share_failed_start() {
+        let server = MockServer::start().await.unwrap();
+        let stream = connect(&server, &ConnectionConfig::default()).await;
+        stream.stop_balance().await.unwrap(); // takes the token, so the next start waits
+
+        let acquire = |stream: Stream| tokio::spawn(async move { stream.subscribe_news().await.map(drop) });
+        let first = acquire(stream.clone());
+        sleep(Duration::from_millis(50)).await;
+        let second = acquire(stream.clone());
+        sleep(Duration::from_millis(50)).await;
+        stream.close().await.unwrap();
+
+        assert!(first.await.unwrap().is_err());
+        assert!(second.await.unwrap().is_err());
+        assert_eq!(count(&server, "getNews"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_acquire_is_released() {
+        let server = MockServer::start().await.unwrap();
+        let stream = connect(&server, &ConnectionConfig::default()).await;
+        stream.stop_balance().await.unwrap(); // takes the token, so the first start waits
+
+        let first = tokio::spawn({
+            let stream = stream.clone();
+            async move { stream.subscribe_news().await }
+        });
+        sleep(Duration::from_millis(20)).await;
+        let second = tokio::time::timeout(Duration::from_millis(50), stream.subscribe_news()).await;
+        assert!(second.is_err());
+
+        drop(first.await.unwrap().unwrap());
+        sleep(Duration::from_millis(300)).await;
+        let commands: Vec<String> = server
+            .received()
+            .into_iter()
+            .map(|received| received.command)
+            .filter(|command| command.ends_with("News"))
+            .collect();
+        assert_eq!(commands, ["getNews", "stopNews"]);
+    }
+
+    #[tokio::test]
+    async fn test_released_stop_precedes_new_start() {
+        let server = MockServer::start().await.unwrap();
+        let stream = connect(&server, &ConnectionConfig::default()).await;
+        let first = stream.subscribe_news().await.unwrap();
+        server.wait_for("getNews", Duration::from_secs(5)).await.unwrap();
+
+        drop(first);
+        sleep(Duration::from_millis(10)).await; // the stop waits for the rate limiter
+        let _second = stream.subscribe_news().await.unwrap();
+        sleep(Duration::from_millis(300)).await;
+
+        let commands: Vec<String> = server
+            .received()
+            .into_iter()
+            .map(|received| received.command)
+            .filter(|command| command.ends_with("News"))
+            .collect();
+        assert_eq!(commands, ["getNews", "stopNews", "getNews"]);
+        assert_eq!(stream.subscriptions(), [Subscription::News]);
+    }
+
+    #[tokio::test]
+    async fn test_record_streams() {
+        use futures::StreamExt;
+
+        let server = MockServer::start().await.unwrap();
+        let stream = connect(&server, &ConnectionConfig::default()).await;
+        let ticks = stream.ticks("EURUSD").await.unwrap();
+        let balances = stream.balances().await.unwrap();
+
+        let tick = |ask| Tick { symbol: String::from("EURUSD"), ask, ..Default::default() };
+        server.push("tickPrices", tick(1.0));
+        server.push("balance", Balance { balance: 100.0, ..Default::default() });
+        server.push("tickPrices", tick(2.0));
+        server.push("tickPrices", tick(3.0));
+
+        let asks: Vec<f64> = ticks
+            .filter_map(|tick| async { tick.ok() })
+            .map(|tick| tick.ask)
+            .take(3)
+            .collect()
+            .await;
+        assert_eq!(asks, [1.0, 2.0, 3.0]);
+
+        let mut balances = balances;
+        assert_eq!(balances.next().await.unwrap().unwrap().balance, 100.0);
+        server.drop_connections();
+        assert!(balances.next().await.unwrap().unwrap_err().is_connection_error());
+        assert!(balances.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_receivers_report_connection_failure() {
+        let server = MockServer::start().await.unwrap();
+        let stream = connect(&server, &ConnectionConfig::default()).await;
+        let mut receiver = stream.subscribe();
+
+        server.push("unknown", json!({}));
+        server.drop_connections();
+
+        assert!(matches!(receiver.recv().await, Err(Error::UnknownRecord { .. })));
+        assert!(receiver.recv().await.unwrap_err().is_connection_error());
+        assert!(stream.subscribe().recv().await.unwrap_err().is_connection_error());
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_watchdog() {
+        let server = MockServer::start().await.unwrap();
+        let config = ConnectionConfig {
+            heartbeat_watchdog: true,
+            heartbeat_timeout: Duration::from_millis(300),
+            ..Default::default()
+        };
+        let stream = connect(&server, &config).await;
+        server.wait_for("getKeepAlive", Duration::from_secs(5)).await.unwrap();
+        assert!(stream.heartbeat().is_none());
+        sleep(Duration::from_millis(500)).await;
+        assert!(!stream.is_closed());
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as i64;
+        server.push("keepAlive", json!({ "timestamp": now }));
+        assert_eq!(timestamp(stream.listen().await.unwrap()), now);
+        let heartbeat = stream.heartbeat().unwrap();
+        assert_eq!(heartbeat.timestamp, now);
+        assert!((0..1000).contains(&heartbeat.delay));
+
+        assert!(matches!(stream.listen().await, Err(Error::StaleConnection)));
+        assert!(stream.is_closed());
     }
 }
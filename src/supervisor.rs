@@ -0,0 +1,273 @@
+use crate::config::ConnectionConfig;
+use crate::credentials::Credentials;
+use crate::data::Record;
+use crate::error::Error;
+use crate::socket::Socket;
+use crate::state::{ConnectionState, StateReporter};
+use crate::stream::{Stream, Subscription};
+use crate::XApi;
+
+use log::*;
+use std::sync::Arc;
+use tokio::sync::{watch, Mutex};
+use tokio::time::{sleep, Duration};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Event delivered by `Supervisor::listen`.
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum Event {
+    /// Record received from the stream.
+    Record(Record),
+    /// The connection was lost and has been re-established. All active subscriptions were re-issued.
+    Reconnected,
+}
+
+#[derive(Debug, Clone)]
+struct Session {
+    generation: u64,
+    xapi: XApi,
+}
+
+/// Exponential backoff used between reconnection attempts.
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    fn delay(&self, attempt: u32) -> Duration {
+        self.initial.saturating_mul(2u32.saturating_pow(attempt)).min(self.max)
+    }
+}
+
+/// Connection supervisor, which transparently reconnects after connection failures.
+///
+/// On a connection error, the supervisor logs in again with exponential backoff, connects the stream
+/// with a fresh `stream_session_id` and re-issues every subscription which was active on the previous stream.
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    credentials: Credentials,
+    config: ConnectionConfig,
+    session: Arc<Mutex<Session>>,
+    reconnecting: Arc<Mutex<()>>,
+    backoff: Backoff,
+    state: StateReporter,
+}
+
+impl Supervisor {
+    pub async fn connect(credentials: &Credentials) -> Result<Supervisor, Error> {
+        Self::connect_with_config(credentials, &ConnectionConfig::default()).await
+    }
+
+    pub async fn connect_with_config(
+        credentials: &Credentials,
+        config: &ConnectionConfig,
+    ) -> Result<Supervisor, Error> {
+        let state = StateReporter::new();
+        let xapi = crate::connect_reporting(credentials, config, state.clone()).await?;
+        Ok(Supervisor {
+            credentials: credentials.clone(),
+            config: config.clone(),
+            session: Arc::new(Mutex::new(Session { generation: 0, xapi })),
+            reconnecting: Arc::new(Mutex::new(())),
+            backoff: Backoff { initial: INITIAL_BACKOFF, max: MAX_BACKOFF },
+            state,
+        })
+    }
+
+    /// Sets the delay before the first reconnection attempt, and the upper limit of the exponential backoff.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Supervisor {
+        self.backoff = Backoff { initial, max };
+        self
+    }
+
+    /// Returns a receiver of the connection state changes, across reconnections.
+    pub fn state(&self) -> watch::Receiver<ConnectionState> {
+        self.state.subscribe()
+    }
+
+    /// Returns the socket of the current connection.
+    pub async fn socket(&self) -> Socket {
+        self.session.lock().await.xapi.socket.clone()
+    }
+
+    /// Returns the stream of the current connection.
+    pub async fn stream(&self) -> Stream {
+        self.session.lock().await.xapi.stream.clone()
+    }
+
+    /// Subscribes on the current stream. The subscription is re-issued after every reconnection.
+    pub async fn add_subscription(&self, subscription: &Subscription) -> Result<(), Error> {
+        self.stream().await.add_subscription(subscription).await
+    }
+
+    /// Unsubscribes on the current stream.
+    pub async fn remove_subscription(&self, subscription: &Subscription) -> Result<(), Error> {
+        self.stream().await.remove_subscription(subscription).await
+    }
+
+    /// Waits for the next stream record. Connection errors are not returned, but handled by reconnecting,
+    /// which is reported as `Event::Reconnected`.
+    pub async fn listen(&self) -> Result<Event, Error> {
+        let session = self.session.lock().await.clone();
+        match session.xapi.stream.listen().await {
+            Ok(record) => Ok(Event::Record(record)),
+            Err(err) if err.is_connection_error() => {
+                warn!("{}, reconnecting", err);
+                self.reconnect_from(session.generation).await?;
+                Ok(Event::Reconnected)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Drops the current connection and connects again.
+    pub async fn reconnect(&self) -> Result<(), Error> {
+        let generation = self.session.lock().await.generation;
+        self.reconnect_from(generation).await
+    }
+
+    async fn reconnect_from(&self, generation: u64) -> Result<(), Error> {
+        // Serializes reconnections, while the current session stays readable during the backoff.
+        let _reconnecting = self.reconnecting.lock().await;
+        if self.session.lock().await.generation != generation {
+            return Ok(()); // already reconnected by another task
+        }
+
+        let state = self.state.next_session();
+        let mut attempt = 0;
+        loop {
+            let delay = self.backoff.delay(attempt);
+            state.set(ConnectionState::Reconnecting);
+            #[cfg(not(feature = "tracing"))]
+            debug!("Reconnecting in {:?}", delay);
+            #[cfg(feature = "tracing")]
+            tracing::info!(
+                attempt = attempt + 1,
+                delay_ms = delay.as_millis() as u64,
+                "Reconnecting"
+            );
+            sleep(delay).await;
+
+            let subscriptions = self.stream().await.subscriptions();
+            match self.resume(&subscriptions, &state).await {
+                Ok(xapi) => {
+                    #[cfg(not(feature = "tracing"))]
+                    info!("Reconnected after {} attempt(s)", attempt + 1);
+                    #[cfg(feature = "tracing")]
+                    tracing::info!(attempts = attempt + 1, "Reconnected");
+                    if let Some(metrics) = &self.config.metrics {
+                        metrics.reconnected();
+                    }
+                    let old = {
+                        let mut session = self.session.lock().await;
+                        session.generation += 1;
+                        std::mem::replace(&mut session.xapi, xapi)
+                    };
+                    old.close().await.ok();
+                    return Ok(());
+                }
+                Err(err) if err.is_connection_error() => {
+                    #[cfg(not(feature = "tracing"))]
+                    warn!("{}, reconnection attempt {} failed", err, attempt + 1);
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(attempt = attempt + 1, error = err.kind(), "Reconnection failed");
+                    attempt = attempt.saturating_add(1);
+                }
+                Err(err) => {
+                    state.set(ConnectionState::Failed);
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    async fn resume(&self, subscriptions: &[Subscription], state: &StateReporter) -> Result<XApi, Error> {
+        let xapi = crate::connect_reporting(&self.credentials, &self.config, state.clone()).await?;
+        for subscription in subscriptions {
+            if let Err(err) = xapi.stream.add_subscription(subscription).await {
+                xapi.close().await.ok();
+                return Err(err);
+            }
+        }
+        Ok(xapi)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockServer;
+
+    use serde_json::json;
+    use tokio::time::timeout;
+
+    #[test]
+    fn test_backoff_delay() {
+        let backoff = Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(10),
+        };
+        assert_eq!(backoff.delay(0), Duration::from_secs(1));
+        assert_eq!(backoff.delay(1), Duration::from_secs(2));
+        assert_eq!(backoff.delay(3), Duration::from_secs(8));
+        assert_eq!(backoff.delay(4), Duration::from_secs(10));
+        assert_eq!(backoff.delay(100), Duration::from_secs(10));
+    }
+
+    fn count(server: &MockServer, command: &str) -> usize {
+        server
+            .received()
+            .iter()
+            .filter(|received| received.command == command)
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_reconnect_replays_subscriptions() {
+        let server = MockServer::start().await.unwrap();
+        let backoff = Duration::from_millis(500);
+        let x = Supervisor::connect(&server.credentials())
+            .await
+            .unwrap()
+            .with_backoff(backoff, backoff);
+        let mut state = x.state();
+        x.add_subscription(&Subscription::Candles { symbol: String::from("EURUSD") })
+            .await
+            .unwrap();
+
+        server.wait_for("getCandles", Duration::from_secs(5)).await.unwrap();
+        server.drop_connections();
+        let listener = tokio::spawn({
+            let x = x.clone();
+            async move { x.listen().await }
+        });
+
+        state
+            .wait_for(|state| *state == ConnectionState::Reconnecting)
+            .await
+            .unwrap();
+        let socket = timeout(Duration::from_millis(100), x.socket()).await;
+        assert!(socket.is_ok(), "the session is locked during the backoff");
+
+        assert!(matches!(listener.await.unwrap(), Ok(Event::Reconnected)));
+        let replayed = async {
+            while count(&server, "getCandles") < 2 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+        timeout(Duration::from_secs(5), replayed).await.unwrap();
+        assert_eq!(count(&server, "login"), 2);
+        assert_eq!(
+            x.stream().await.subscriptions(),
+            [Subscription::Candles { symbol: String::from("EURUSD") }]
+        );
+
+        server.push("keepAlive", json!({ "timestamp": 1 }));
+        assert!(matches!(x.listen().await, Ok(Event::Record(Record::KeepAlive(_)))));
+    }
+}
@@ -0,0 +1,209 @@
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+use crate::error::Error;
+
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+use tokio_tungstenite::Connector;
+
+/// TLS settings of the WebSocket connections.
+///
+/// The backend is selected with the `native-tls` (default) or the `rustls-tls` cargo feature.
+/// With both enabled, `native-tls` is used.
+#[derive(Clone, Default)]
+pub enum TlsConfig {
+    /// Backend defaults: the system roots for `native-tls`, the Mozilla roots bundled in `webpki-roots`
+    /// for `rustls-tls`. The system roots are not used by `rustls-tls`.
+    #[default]
+    Default,
+    /// Trusts the given PEM encoded certificates, in addition to the default roots.
+    RootCertificates(Vec<Vec<u8>>),
+    /// Prebuilt `native-tls` connector.
+    #[cfg(feature = "native-tls")]
+    NativeTls(native_tls::TlsConnector),
+    /// Prebuilt `rustls` client config.
+    #[cfg(feature = "rustls-tls")]
+    Rustls(std::sync::Arc<rustls::ClientConfig>),
+}
+
+impl std::fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TlsConfig::Default => write!(f, "Default"),
+            TlsConfig::RootCertificates(certificates) => write!(f, "RootCertificates({})", certificates.len()),
+            #[cfg(feature = "native-tls")]
+            TlsConfig::NativeTls(_) => write!(f, "NativeTls"),
+            #[cfg(feature = "rustls-tls")]
+            TlsConfig::Rustls(_) => write!(f, "Rustls"),
+        }
+    }
+}
+
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+impl TlsConfig {
+    /// Returns the connector to establish a connection with. `None` means the backend default.
+    pub(crate) fn connector(&self) -> Result<Option<Connector>, Error> {
+        match self {
+            TlsConfig::Default => Ok(None),
+            TlsConfig::RootCertificates(certificates) => root_certificates_connector(certificates).map(Some),
+            #[cfg(feature = "native-tls")]
+            TlsConfig::NativeTls(connector) => Ok(Some(Connector::NativeTls(connector.clone()))),
+            #[cfg(feature = "rustls-tls")]
+            TlsConfig::Rustls(config) => Ok(Some(Connector::Rustls(config.clone()))),
+        }
+    }
+}
+
+#[cfg(any(feature = "native-tls", feature = "rustls-tls"))]
+fn tls_error(reason: impl ToString) -> Error {
+    Error::TlsError { reason: reason.to_string() }
+}
+
+#[cfg(feature = "native-tls")]
+fn root_certificates_connector(certificates: &[Vec<u8>]) -> Result<Connector, Error> {
+    let mut builder = native_tls::TlsConnector::builder();
+    for pem in certificates {
+        for certificate in pem_certificates(pem)? {
+            builder.add_root_certificate(native_tls::Certificate::from_pem(certificate).map_err(tls_error)?);
+        }
+    }
+    Ok(Connector::NativeTls(builder.build().map_err(tls_error)?))
+}
+
+/// Splits a PEM bundle into its certificates, as `native_tls::Certificate::from_pem` reads only the first one.
+#[cfg(feature = "native-tls")]
+fn pem_certificates(pem: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    const BEGIN: &[u8] = b"-----BEGIN CERTIFICATE-----";
+    const END: &[u8] = b"-----END CERTIFICATE-----";
+    let find = |data: &[u8], marker: &[u8]| data.windows(marker.len()).position(|window| window == marker);
+
+    let mut certificates = Vec::new();
+    let mut rest = pem;
+    while let Some(begin) = find(rest, BEGIN) {
+        let end = find(&rest[begin..], END)
+            .map(|end| begin + end + END.len())
+            .ok_or_else(|| tls_error("Invalid PEM certificate"))?;
+        certificates.push(&rest[begin..end]);
+        rest = &rest[end..];
+    }
+    if certificates.is_empty() {
+        return Err(tls_error("Invalid PEM certificate"));
+    }
+    Ok(certificates)
+}
+
+#[cfg(all(feature = "rustls-tls", not(feature = "native-tls")))]
+fn root_certificates_connector(certificates: &[Vec<u8>]) -> Result<Connector, Error> {
+    let mut config = rustls::ClientConfig::new();
+    config
+        .root_store
+        .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+    for pem in certificates {
+        let (valid, invalid) = config
+            .root_store
+            .add_pem_file(&mut pem.as_slice())
+            .map_err(|_| tls_error("Invalid PEM certificate"))?;
+        if valid == 0 || invalid > 0 {
+            return Err(tls_error("Invalid PEM certificate"));
+        }
+    }
+    Ok(Connector::Rustls(std::sync::Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConnectionConfig;
+    use crate::mock::MockServer;
+
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_native_tls::native_tls;
+
+    const CA: &[u8] = include_bytes!("../tests/fixtures/tls/ca.pem");
+    const CERTIFICATE: &[u8] = include_bytes!("../tests/fixtures/tls/localhost.pem");
+    const KEY: &[u8] = include_bytes!("../tests/fixtures/tls/localhost.key");
+
+    /// Local TLS server for `localhost`, forwarding to the mock server. Served with `native-tls` for both backends.
+    async fn tls_server(server: &MockServer) -> u16 {
+        let identity = native_tls::Identity::from_pkcs8(CERTIFICATE, KEY).unwrap();
+        let acceptor = tokio_native_tls::TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let target = server.url().trim_start_matches("ws://").to_owned();
+        tokio::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                let target = target.clone();
+                tokio::spawn(async move {
+                    let Ok(mut client) = acceptor.accept(client).await else {
+                        return;
+                    };
+                    let mut server = TcpStream::connect(target).await.unwrap();
+                    tokio::io::copy_bidirectional(&mut client, &mut server).await.ok();
+                });
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_custom_root_certificate() {
+        let server = MockServer::start().await.unwrap();
+        let port = tls_server(&server).await;
+        let credentials = crate::Credentials {
+            host: format!("wss://localhost:{}", port),
+            ..server.credentials()
+        };
+
+        let err = crate::connect(&credentials).await.unwrap_err();
+        assert!(err.is_connection_error());
+
+        let config = ConnectionConfig {
+            tls: TlsConfig::RootCertificates(vec![CA.to_vec()]),
+            ..Default::default()
+        };
+        let x = crate::connect_with_config(&credentials, &config).await.unwrap();
+        assert!(x.socket.ping().await.is_ok());
+
+        let bundle = ConnectionConfig {
+            tls: TlsConfig::RootCertificates(vec![[CERTIFICATE, CA].concat()]),
+            ..Default::default()
+        };
+        let x = crate::connect_with_config(&credentials, &bundle).await.unwrap();
+        assert!(x.socket.ping().await.is_ok());
+    }
+
+    #[cfg(feature = "rustls-tls")]
+    #[tokio::test]
+    async fn test_rustls_config() {
+        let server = MockServer::start().await.unwrap();
+        let port = tls_server(&server).await;
+        let credentials = crate::Credentials {
+            host: format!("wss://localhost:{}", port),
+            ..server.credentials()
+        };
+
+        let mut rustls = rustls::ClientConfig::new();
+        rustls.root_store.add_pem_file(&mut &CA[..]).unwrap();
+        let config = ConnectionConfig {
+            tls: TlsConfig::Rustls(std::sync::Arc::new(rustls)),
+            ..Default::default()
+        };
+        let x = crate::connect_with_config(&credentials, &config).await.unwrap();
+        assert!(x.socket.ping().await.is_ok());
+
+        let untrusted = ConnectionConfig {
+            tls: TlsConfig::Rustls(std::sync::Arc::new(rustls::ClientConfig::new())),
+            ..Default::default()
+        };
+        let err = crate::connect_with_config(&credentials, &untrusted).await.unwrap_err();
+        assert!(err.is_connection_error());
+    }
+
+    #[test]
+    fn test_invalid_certificate() {
+        let tls = TlsConfig::RootCertificates(vec![b"not a certificate".to_vec()]);
+        assert!(matches!(tls.connector(), Err(Error::TlsError { .. })));
+        let truncated = CA[..CA.len() / 2].to_vec();
+        let tls = TlsConfig::RootCertificates(vec![truncated]);
+        assert!(matches!(tls.connector(), Err(Error::TlsError { .. })));
+    }
+}